@@ -43,12 +43,12 @@ in
           recipe_dir = "/home/user/.config/mkdev/my_recipes";
 
           subs = {
-            day = "date +%d";
+            day = "mk::date(%d)";
             dir = "mk::dir";
             name = "mk::name";
-            month = "date +%m";
-            user = "whoami";
-            year = "date +%Y";
+            month = "mk::date(%m)";
+            user = "mk::user";
+            year = "mk::date(%Y)";
           };
         };
       '';
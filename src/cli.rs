@@ -1,7 +1,9 @@
 //! The command line interface for mkdev.
 #![deny(missing_docs)]
+use crate::conflict_strategy::ConflictStrategy;
 use crate::output_type::OutputType;
 use crate::recipe_completer::recipe_completer;
+use crate::symlink_strategy::SymlinkStrategy;
 
 use std::path::PathBuf;
 
@@ -62,10 +64,14 @@ pub enum Commands {
 
 #[derive(Parser, Clone, Debug)]
 pub struct Evoke {
-    /// The recipe(s) to build
+    /// The recipe(s) to build. Use `-` to read a recipe from stdin
     #[arg(add = ArgValueCompleter::new(recipe_completer))]
     pub recipes: Vec<String>,
 
+    /// Build a recipe from a TOML or JSON file, without installing it
+    #[arg(short, long, value_name = "FILE", action = ArgAction::Append)]
+    pub file: Vec<PathBuf>,
+
     /// Target directory for recipe output
     #[arg(last = true)]
     pub dir_name: Option<String>,
@@ -75,13 +81,45 @@ pub struct Evoke {
     #[arg(short, long)]
     pub name: Option<String>,
 
+    /// Set a substitution to a literal value, skipping any prompt for it
+    #[arg(long, value_name = "KEY=VALUE", action = ArgAction::Append)]
+    pub set: Vec<String>,
+
+    /// Run shell commands that come with the recipe(s) without asking for approval
+    #[arg(long)]
+    pub allow_commands: bool,
+
+    /// Fail instead of running any shell command; only built-in functions are evaluated
+    #[arg(long, conflicts_with = "allow_commands")]
+    pub no_shell: bool,
+
+    /// Don't run the recipe(s)' post-evoke hooks
+    #[arg(long)]
+    pub no_hooks: bool,
+
+    /// Create symlinks from the recipe(s) even if they point outside the directory
+    #[arg(long)]
+    pub allow_external_links: bool,
+
     /// Print debug info during build
     #[arg(short, long)]
     pub verbose: bool,
 
-    /// Supress warnings about destructive actions
+    /// Supress warnings about destructive actions (same as `--on-conflict overwrite`)
     #[arg(short, long)]
     pub suppress_warnings: bool,
+
+    /// What to do when a file already exists
+    #[arg(long, value_name = "STRATEGY")]
+    pub on_conflict: Option<ConflictStrategy>,
+
+    /// Three-way merge recipe changes into files written by a previous evoke
+    #[arg(short, long)]
+    pub update: bool,
+
+    /// Print the directories and files that would be written without touching the disk
+    #[arg(long)]
+    pub dry_run: bool,
 }
 
 #[derive(Parser, Debug)]
@@ -89,6 +127,18 @@ pub struct Imprint {
     /// The name of the recipe to imprint.
     pub recipe: String,
 
+    /// Paths under the root to imprint, instead of the whole root
+    #[arg(value_name = "PATHS")]
+    pub paths: Vec<PathBuf>,
+
+    /// The directory that the recipe's paths are relative to [default: the cwd]
+    #[arg(long, value_name = "DIR")]
+    pub root: Option<PathBuf>,
+
+    /// Imprint the tracked files at a git revision (e.g. a tag) instead of the working tree
+    #[arg(long, value_name = "REV")]
+    pub git_rev: Option<String>,
+
     /// Description to be associated with recipe
     #[arg(short, long)]
     pub description: Option<String>,
@@ -97,17 +147,39 @@ pub struct Imprint {
     #[arg(short, long)]
     pub suppress_warnings: bool,
 
+    /// Update the contents of the existing recipe, keeping its description, languages,
+    /// substitutions, and other settings
+    #[arg(short, long)]
+    pub update: bool,
+
     /// Write the recipe as a Nix expression & save it to FILE
     #[arg(short = 'n', long, value_name = "FILE")]
     pub to_nix: Option<PathBuf>,
 
+    /// Paths/globs to include in the recipe; everything else is left out
+    #[arg(short, long, value_name = "FILE/GLOB", action = ArgAction::Append)]
+    pub include: Vec<String>,
+
     /// Paths/globs to exclude from the recipe
     #[arg(short, long, value_name = "FILE/GLOB", action = ArgAction::Append)]
     pub exclude: Vec<String>,
 
+    /// Only descend this many directories below each path
+    #[arg(long, value_name = "N")]
+    pub max_depth: Option<usize>,
+
     /// Disable default filters (e.g., .gitignore files)
     #[arg(long)]
     pub no_filter: bool,
+
+    /// What to do with symlinks
+    #[arg(long, value_name = "STRATEGY", default_value = "skip")]
+    pub symlinks: SymlinkStrategy,
+
+    /// Replace a literal value (and its case variants) with a substitution token in file contents
+    /// and paths
+    #[arg(long, value_name = "KEY=VALUE", action = ArgAction::Append)]
+    pub templatize: Vec<String>,
 }
 
 #[derive(Parser, Debug)]
@@ -1,14 +1,23 @@
 //! mkdev's user configuration file.
+//!
+//! The user's config can be extended per project by a `.mkdev.toml` in the cwd or any of its
+//! parents. The nearest one is merged over the user's config, so a project can add its own
+//! substitutions, recipe directories, and display settings.
 use crate::display::DisplayConfig;
 use crate::mkdev_error::{Error, ResultExt};
 
 use std::collections::HashMap;
 use std::default::Default;
+use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::OnceLock;
 
 use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+
+/// The name of a project-local config file.
+pub const PROJECT_CONFIG_NAME: &str = ".mkdev.toml";
 
 // There should only ever be one instance of the config to prevent
 // multiple intialisations
@@ -20,6 +29,12 @@ pub struct Config {
     /// Path to where recipes should be read from/saved to
     /// Default: None (evaluates to ~/.local/share/mkdev on Linux)
     pub recipe_dir: Option<PathBuf>,
+    /// Extra directories to read recipes from, in order of precedence. Recipes in earlier
+    /// directories shadow recipes of the same name in later ones. `recipe_dir` is searched last,
+    /// unless it is listed here, and is the only directory that recipes are saved to.
+    /// Default: []
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recipe_path: Vec<PathBuf>,
     /// User defined variables for recipe building
     /// Default: See `Config::default`
     #[serde(default = "default_subs")]
@@ -28,6 +43,12 @@ pub struct Config {
     /// Default: See `DisplayConfig::default`
     #[serde(default)]
     pub recipe_fmt: DisplayConfig,
+    /// The project config merged over the user's config, if any
+    #[serde(skip)]
+    pub project_file: Option<PathBuf>,
+    /// The substitutions that the project config provides
+    #[serde(skip)]
+    pub project_subs: HashMap<String, String>,
 }
 
 impl Config {
@@ -57,8 +78,40 @@ impl Config {
     /// Private api for loading the config if it is not already loaded.
     ///
     /// The file is read in from the default location (or the user-provided override), or a default
-    /// is provided.
+    /// is provided. A project config, if there is one, is then merged over it.
     fn load() -> Result<Config, Error> {
+        let mut table = Self::load_user()?;
+        let mut project_subs = HashMap::new();
+
+        let project_file = find_project_config();
+        if let Some(path) = &project_file {
+            let contents =
+                fs::read_to_string(path).context(&format!("unable to read {}", path.display()))?;
+            let context = path.to_string_lossy();
+            let mut project: Table = toml::from_str(&contents).context(&context)?;
+
+            if let Some(subs) = project.get("subs") {
+                project_subs = subs.clone().try_into().context(&context)?;
+            }
+
+            let project_dir = path
+                .parent()
+                .expect("Config files are always in a directory");
+            resolve_paths(&mut project, project_dir);
+            merge(&mut table, project);
+        }
+
+        let cfg: Config = table.try_into().context("configuration file")?;
+
+        Ok(Config {
+            project_file,
+            project_subs,
+            ..cfg
+        })
+    }
+
+    /// Reads the user's config file, writing a default one if there is none.
+    fn load_user() -> Result<Table, Error> {
         // The config file is overridden, or is default
         let config_file = match CONFIG_PATH_OVERRIDE.get() {
             Some(path) => path.clone(),
@@ -80,17 +133,72 @@ impl Config {
             let serialized_default = toml::to_string(&cfg)
                 .expect("Default configuration should always serialize correctly");
 
-            fs::write(config_file, serialized_default)
+            fs::write(config_file, &serialized_default)
                 .context("unable to write default configuration file")?;
 
-            Ok(cfg)
+            let table = toml::from_str(&serialized_default)
+                .expect("The default config was just serialised");
+
+            Ok(table)
         } else {
             let cfg_contents =
                 fs::read_to_string(config_file).context("unable to read configuration file")?;
 
-            let cfg: Config = toml::from_str(&cfg_contents).context("configuration file")?;
+            toml::from_str(&cfg_contents).context("configuration file")
+        }
+    }
+}
+
+/// Finds the nearest project config in the cwd or any of its parents.
+fn find_project_config() -> Option<PathBuf> {
+    env::current_dir()
+        .ok()?
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_NAME))
+        .find(|path| path.is_file())
+}
+
+/// Makes the recipe directories in a project config relative to the project, not the cwd.
+///
+/// A project's `recipe_dir` is searched before its `recipe_path`, but it never replaces the user's
+/// own `recipe_dir`, so personal recipes stay on the recipe path and are still the only ones that
+/// mkdev writes to.
+fn resolve_paths(project: &mut Table, project_dir: &Path) {
+    let resolve = |value: &mut Value| {
+        if let Value::String(path) = value {
+            *path = project_dir.join(&*path).to_string_lossy().into_owned();
+        }
+    };
+
+    let mut dirs = match project.remove("recipe_path") {
+        Some(Value::Array(dirs)) => dirs,
+        Some(other) => vec![other],
+        None => vec![],
+    };
+    if let Some(dir) = project.remove("recipe_dir") {
+        dirs.insert(0, dir);
+    }
+    if !dirs.is_empty() {
+        dirs.iter_mut().for_each(resolve);
+        project.insert("recipe_path".into(), Value::Array(dirs));
+    }
+}
 
-            Ok(cfg)
+/// Merges `overlay` into `base`.
+///
+/// Tables are merged key by key and arrays are prepended, so that the overlay's entries take
+/// precedence; any other value is replaced.
+fn merge(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(overlay)) => merge(base, overlay),
+            (Some(Value::Array(base)), Value::Array(mut overlay)) => {
+                overlay.append(base);
+                *base = overlay;
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
         }
     }
 }
@@ -99,16 +207,19 @@ impl Config {
 ///
 /// mk::dir and mk::name are special reserved values provided directly by mkdev. The other values
 /// are some simple defaults to get the currently logged in user's username or to get the
-/// components of the date.
-fn default_subs() -> HashMap<String, String> {
+/// components of the date, using built-in functions so that no shell is needed.
+///
+/// These are also the lowest layer of substitutions at build time, so a user config that only
+/// defines some substitutions still gets the rest.
+pub fn default_subs() -> HashMap<String, String> {
     HashMap::from_iter(
         [
             ("dir", "mk::dir"),
             ("name", "mk::name"),
-            ("user", "whoami"),
-            ("day", "date +%d"),
-            ("month", "date +%m"),
-            ("year", "date +%Y"),
+            ("user", "mk::user"),
+            ("day", "mk::date(%d)"),
+            ("month", "mk::date(%m)"),
+            ("year", "mk::date(%Y)"),
         ]
         .iter()
         .map(|(k, v)| (k.to_string(), v.to_string())),
@@ -118,13 +229,68 @@ fn default_subs() -> HashMap<String, String> {
 impl Default for Config {
     fn default() -> Self {
         let recipe_dir = None;
+        let recipe_path = vec![];
         let subs = default_subs();
         let recipe_fmt = DisplayConfig::default();
 
         Self {
             recipe_dir,
+            recipe_path,
             subs,
             recipe_fmt,
+            project_file: None,
+            project_subs: HashMap::new(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn project_config_merges_over_user_config() {
+        let mut user: Table = toml::from_str(
+            r#"
+            recipe_dir = "/home/recipes"
+            recipe_path = ["/team"]
+            [subs]
+            user = "mk::user"
+            owner = "me"
+            [recipe_fmt]
+            name_bold = true
+            "#,
+        )
+        .unwrap();
+        let mut project: Table = toml::from_str(
+            r#"
+            recipe_dir = "recipes"
+            recipe_path = ["shared", "/abs"]
+            [subs]
+            owner = "platform-team"
+            [recipe_fmt]
+            lang_colour = false
+            "#,
+        )
+        .unwrap();
+
+        resolve_paths(&mut project, Path::new("/repo"));
+        merge(&mut user, project);
+        let cfg: Config = user.try_into().unwrap();
+
+        assert_eq!(cfg.recipe_dir.unwrap(), Path::new("/home/recipes"));
+        assert_eq!(
+            cfg.recipe_path,
+            [
+                Path::new("/repo/recipes"),
+                Path::new("/repo/shared"),
+                Path::new("/abs"),
+                Path::new("/team")
+            ]
+        );
+        assert_eq!(cfg.subs["owner"], "platform-team");
+        assert_eq!(cfg.subs["user"], "mk::user");
+        assert!(cfg.recipe_fmt.name_bold);
+        assert!(!cfg.recipe_fmt.lang_colour);
+    }
+}
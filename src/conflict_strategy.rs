@@ -0,0 +1,20 @@
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, ValueEnum, Default, PartialEq, Eq)]
+/// What `mk evoke` should do when a destination file already exists. Used by the --on-conflict
+/// flag
+pub enum ConflictStrategy {
+    /// Stop before anything is written
+    #[default]
+    Abort,
+    /// Keep the existing file
+    Skip,
+    /// Replace the existing file
+    Overwrite,
+    /// Rename the existing file to `*.orig`, then write the new one
+    Backup,
+    /// Add the new contents to the end of the existing file
+    Append,
+    /// Show a diff and ask what to do for each file
+    Prompt,
+}
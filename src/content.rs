@@ -4,11 +4,14 @@ use ignore::overrides::OverrideBuilder;
 use crate::cli::Imprint;
 use crate::mkdev_error;
 use crate::mkdev_error::ResultExt;
+use crate::recipe::MANIFEST_NAME;
+use crate::symlink_strategy::SymlinkStrategy;
 
 use std::cmp::Ordering;
+use std::fmt;
 use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use ignore::{Walk, WalkBuilder};
 use serde::{Deserialize, Serialize};
@@ -18,6 +21,8 @@ use serde::{Deserialize, Serialize};
 #[serde(untagged)]
 pub enum RecipeItem {
     File(File),
+    Binary(Binary),
+    Symlink(Symlink),
     Directory(PathBuf),
 }
 
@@ -26,16 +31,90 @@ impl RecipeItem {
     pub fn name(&self) -> String {
         let name = match self {
             RecipeItem::File(file) => file.name.to_string_lossy(),
+            RecipeItem::Binary(file) => file.name.to_string_lossy(),
+            RecipeItem::Symlink(link) => link.name.to_string_lossy(),
             RecipeItem::Directory(dir) => dir.to_string_lossy(),
         };
 
         name.into()
     }
 
-    /// Constructs a new `RecipeItem::File` variant
+    /// Returns the path of the `RecipeItem`, relative to the root of the recipe.
+    pub fn path(&self) -> &Path {
+        match self {
+            RecipeItem::File(file) => &file.name,
+            RecipeItem::Binary(file) => &file.name,
+            RecipeItem::Symlink(link) => &link.name,
+            RecipeItem::Directory(dir) => dir,
+        }
+    }
+
+    /// Whether two items are the same in every way, rather than just having the same path (which
+    /// is all that `==` compares).
+    pub fn is_identical(&self, other: &Self) -> bool {
+        use RecipeItem::*;
+        match (self, other) {
+            (File(a), File(b)) => a.name == b.name && a.content == b.content && a.mode == b.mode,
+            (Binary(a), Binary(b)) => a.name == b.name && a.bytes == b.bytes && a.mode == b.mode,
+            (Symlink(a), Symlink(b)) => a.name == b.name && a.target == b.target,
+            (Directory(a), Directory(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    /// Moves the `RecipeItem` into a subdirectory.
+    pub fn mounted(self, dir: &Path) -> Self {
+        match self {
+            RecipeItem::File(file) => RecipeItem::File(File {
+                name: dir.join(file.name),
+                ..file
+            }),
+            RecipeItem::Binary(file) => RecipeItem::Binary(Binary {
+                name: dir.join(file.name),
+                ..file
+            }),
+            RecipeItem::Symlink(link) => RecipeItem::Symlink(Symlink {
+                name: dir.join(link.name),
+                ..link
+            }),
+            RecipeItem::Directory(name) => RecipeItem::Directory(dir.join(name)),
+        }
+    }
+
+    /// Makes the `RecipeItem`'s path relative to `root`, if it is under it.
+    fn relative_to(self, root: &Path) -> Self {
+        let relative = |path: PathBuf| match path.strip_prefix(root) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => path,
+        };
+
+        match self {
+            RecipeItem::File(file) => RecipeItem::File(File {
+                name: relative(file.name),
+                ..file
+            }),
+            RecipeItem::Binary(file) => RecipeItem::Binary(Binary {
+                name: relative(file.name),
+                ..file
+            }),
+            RecipeItem::Symlink(link) => RecipeItem::Symlink(Symlink {
+                name: relative(link.name),
+                ..link
+            }),
+            RecipeItem::Directory(name) => RecipeItem::Directory(relative(name)),
+        }
+    }
+
+    /// Constructs a new `RecipeItem::File` variant, or a `RecipeItem::Binary` variant if the file
+    /// is not valid UTF-8.
     fn file(name: PathBuf) -> io::Result<Self> {
-        let f = File::new(name)?;
-        Ok(Self::File(f))
+        match File::new(name.clone()) {
+            Ok(f) => Ok(Self::File(f)),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                Ok(Self::Binary(Binary::new(name)?))
+            }
+            Err(e) => Err(e),
+        }
     }
 
     /// Constructs a new `RecipeItem::Directory` variant
@@ -44,30 +123,162 @@ impl RecipeItem {
     }
 }
 
+/// The mode that most files have (rw-r--r--), which is left out of imprinted recipes.
+const DEFAULT_MODE: u32 = 0o644;
+
 /// A file.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct File {
     pub name: PathBuf,
     pub content: String,
+    /// The Unix permission bits of the file (Optional, otherwise new files get the user's default)
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "octal_mode")]
+    pub mode: Option<u32>,
 }
 
 impl File {
     pub fn new(name: PathBuf) -> io::Result<Self> {
         let content = fs::read_to_string(&name)?;
+        let mode = read_mode(&name)?;
+
+        Ok(Self {
+            name,
+            content,
+            mode,
+        })
+    }
+}
+
+/// A file that is not text, and so is stored base64 encoded and never has substitutions applied.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Binary {
+    pub name: PathBuf,
+    #[serde(rename = "binary", with = "base64_bytes")]
+    pub bytes: Vec<u8>,
+    /// The Unix permission bits of the file (Optional, otherwise new files get the user's default)
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "octal_mode")]
+    pub mode: Option<u32>,
+}
+
+impl Binary {
+    pub fn new(name: PathBuf) -> io::Result<Self> {
+        let bytes = fs::read(&name)?;
+        let mode = read_mode(&name)?;
+
+        Ok(Self { name, bytes, mode })
+    }
+
+    /// Displays the size of the file in human readable units.
+    pub fn display_size(&self) -> String {
+        let mut size = self.bytes.len() as f64;
+        for unit in ["B", "KiB", "MiB"] {
+            if size < 1024.0 {
+                return match unit {
+                    "B" => format!("{size} {unit}"),
+                    _ => format!("{size:.1} {unit}"),
+                };
+            }
+            size /= 1024.0;
+        }
+
+        format!("{size:.1} GiB")
+    }
+}
+
+/// The bytes would drown out everything else, so only their size is shown.
+impl fmt::Debug for Binary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Binary")
+            .field("name", &self.name)
+            .field("bytes", &self.display_size())
+            .field("mode", &self.mode.map(|mode| format!("{mode:o}")))
+            .finish()
+    }
+}
+
+/// A symbolic link. The target may contain substitutions.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Symlink {
+    pub name: PathBuf,
+    #[serde(rename = "symlink")]
+    pub target: PathBuf,
+}
+
+impl Symlink {
+    pub fn new(name: PathBuf) -> io::Result<Self> {
+        let target = fs::read_link(&name)?;
+
+        Ok(Self { name, target })
+    }
+}
+
+/// Reads the permission bits of a file, unless they are `DEFAULT_MODE`.
+#[cfg(unix)]
+fn read_mode(path: &Path) -> io::Result<Option<u32>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = fs::metadata(path)?.permissions().mode() & 0o777;
+    Ok((mode != DEFAULT_MODE).then_some(mode))
+}
+
+/// Other platforms have no permission bits to read.
+#[cfg(not(unix))]
+fn read_mode(_path: &Path) -> io::Result<Option<u32>> {
+    Ok(None)
+}
+
+/// (De)serialises bytes as a base64 string.
+mod base64_bytes {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(D::Error::custom)
+    }
+}
+
+/// (De)serialises permission bits as an octal string, e.g. `"755"`.
+mod octal_mode {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
 
-        Ok(Self { name, content })
+    pub fn serialize<S: Serializer>(mode: &Option<u32>, serializer: S) -> Result<S::Ok, S::Error> {
+        match mode {
+            Some(mode) => serializer.serialize_str(&format!("{mode:o}")),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let octal = String::deserialize(deserializer)?;
+        match u32::from_str_radix(&octal, 8) {
+            Ok(mode) if mode <= 0o777 => Ok(Some(mode)),
+            _ => Err(D::Error::custom(format!("invalid file mode `{octal}`"))),
+        }
     }
 }
 
-/// Recursively detects and saves every file and subdirectory in the current working directory.
+/// Recursively detects and saves every file and subdirectory that the walk finds, relative to
+/// `root`.
 ///
-/// Standard ignore filters are applied (.gitignore, .ignore, etc.), and symlinks are ignored.
-pub fn make_contents(walk: Walk) -> io::Result<Vec<RecipeItem>> {
-    let cwd = std::env::current_dir()?;
+/// Standard ignore filters are applied (.gitignore, .ignore, etc.). Symlinks are skipped or kept
+/// according to `symlinks`; when they are followed, the walk has already resolved them.
+pub fn make_contents(
+    walk: Walk,
+    root: &Path,
+    symlinks: SymlinkStrategy,
+) -> io::Result<Vec<RecipeItem>> {
     let mut out = vec![];
 
     for file in walk.flatten() {
-        if file.path() == cwd {
+        if file.path() == root {
             continue;
         }
 
@@ -75,51 +286,141 @@ pub fn make_contents(walk: Walk) -> io::Result<Vec<RecipeItem>> {
             .file_type()
             .expect("This can only be `None` if this is stdin, which is not allowed");
 
-        let mut path = file.into_path();
-
-        if path.starts_with(&cwd) {
-            path = path
-                .strip_prefix(&cwd)
-                .expect("This is checked with `starts_with`")
-                .into();
-        }
-
+        let path = file.into_path();
         let (is_file, is_dir, is_symlink) = (data.is_file(), data.is_dir(), data.is_symlink());
 
         // Make File or Directory variant as necessary
-        match (is_file, is_dir, is_symlink) {
-            (true, false, false) => out.push(RecipeItem::file(path)?),
-            (false, true, false) => out.push(RecipeItem::dir(path)),
-            // ignore symlinks (TODO: allow customisation with CLI)
-            (false, false, true) => continue,
+        let item = match (is_file, is_dir, is_symlink) {
+            (true, false, false) => RecipeItem::file(path)?,
+            (false, true, false) => RecipeItem::dir(path),
+            (false, false, true) => match symlinks {
+                SymlinkStrategy::Preserve => RecipeItem::Symlink(Symlink::new(path)?),
+                // Followed symlinks that still show up as links are broken, so there is nothing
+                // to inline
+                SymlinkStrategy::Skip | SymlinkStrategy::Follow => continue,
+            },
             // All of these methods' results are mutually exclusive
             // see: https://doc.rust-lang.org/nightly/std/fs/struct.FileType.html
             _ => unreachable!(),
-        }
+        };
+
+        out.push(item.relative_to(root));
     }
 
     out.sort();
     Ok(out)
 }
 
-/// Constructs a recursive walk.
+/// Finds the directory that an imprinted recipe is relative to: `--root`, or the cwd.
+pub fn imprint_root(args: &Imprint) -> Result<PathBuf, mkdev_error::Error> {
+    let cwd = std::env::current_dir().context("unable to get cwd")?;
+    let root = cwd.join(args.root.as_deref().unwrap_or(Path::new(".")));
+
+    canonical(&root)
+}
+
+/// Finds the paths under `root` to walk: the ones given on the command line (relative to the
+/// root), or the root itself if there are none.
+pub fn imprint_paths(args: &Imprint, root: &Path) -> Result<Vec<PathBuf>, mkdev_error::Error> {
+    if args.paths.is_empty() {
+        return Ok(vec![root.to_path_buf()]);
+    }
+
+    let paths = args
+        .paths
+        .iter()
+        .map(|path| canonical(&root.join(path)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let outside: Vec<_> = paths
+        .iter()
+        .filter(|path| !path.starts_with(root))
+        .map(|path| path.display().to_string())
+        .collect();
+    if !outside.is_empty() {
+        let what = format!("path(s), expected paths under {}", root.display());
+        return Err(mkdev_error::Error::Invalid(what, Some(outside)));
+    }
+
+    Ok(outermost(paths))
+}
+
+/// Leaves out every path that is also under another one, since it is walked with that one.
+fn outermost(mut paths: Vec<PathBuf>) -> Vec<PathBuf> {
+    // A path sorts after every path it is under
+    paths.sort();
+
+    let mut outer: Vec<PathBuf> = vec![];
+    for path in paths {
+        if !outer.iter().any(|other| path.starts_with(other)) {
+            outer.push(path);
+        }
+    }
+
+    outer
+}
+
+/// Resolves a path that must exist to its canonical form.
+fn canonical(path: &Path) -> Result<PathBuf, mkdev_error::Error> {
+    path.canonicalize()
+        .context(&format!("unable to find {}", path.display()))
+}
+
+/// Constructs a recursive walk over `paths`, with overrides relative to `root`.
 ///
 /// This function exists to allow users to override the walk behaviour.
-pub fn build_walk(args: &Imprint) -> Result<Walk, mkdev_error::Error> {
-    let cwd = std::env::current_dir().context("could not build walk object")?;
-
-    let mut ob = OverrideBuilder::new(&cwd);
+pub fn build_walk(
+    args: &Imprint,
+    root: &Path,
+    paths: &[PathBuf],
+) -> Result<Walk, mkdev_error::Error> {
+    let mut ob = OverrideBuilder::new(root);
+    // Any include means that everything else is left out
+    for over in &args.include {
+        ob.add(over)?;
+    }
     for over in &args.exclude {
         ob.add(&format!("!{over}"))?;
     }
+    // Evoke manifests describe a generated project, not its contents
+    if !args.no_filter {
+        ob.add(&format!("!/{MANIFEST_NAME}"))?;
+    }
     let user_filters = ob.build()?;
 
-    Ok(WalkBuilder::new(&cwd)
+    let (first, rest) = paths
+        .split_first()
+        .expect("There is always at least one path");
+    let mut walk = WalkBuilder::new(first);
+    for path in rest {
+        walk.add(path);
+    }
+
+    Ok(walk
         .standard_filters(!args.no_filter)
+        .follow_links(args.symlinks == SymlinkStrategy::Follow)
+        .max_depth(args.max_depth)
         .overrides(user_filters)
         .build())
 }
 
+/// Leaves out directories with nothing in them, e.g. the ones that `--include` left empty.
+pub fn without_empty_dirs(contents: Vec<RecipeItem>) -> Vec<RecipeItem> {
+    let occupied: Vec<PathBuf> = contents
+        .iter()
+        .filter(|item| !matches!(item, RecipeItem::Directory(_)))
+        .map(|item| item.path().to_path_buf())
+        .collect();
+
+    contents
+        .into_iter()
+        .filter(|item| match item {
+            RecipeItem::Directory(dir) => occupied.iter().any(|path| path.starts_with(dir)),
+            _ => true,
+        })
+        .collect()
+}
+
 impl PartialEq for RecipeItem {
     fn eq(&self, other: &Self) -> bool {
         self.cmp(other) == Ordering::Equal
@@ -138,10 +439,94 @@ impl Ord for RecipeItem {
     fn cmp(&self, other: &Self) -> Ordering {
         use RecipeItem::*;
         match (self, other) {
-            (Directory(_), File(_)) => Ordering::Less,
-            (File(_), Directory(_)) => Ordering::Greater,
-            (File(a), File(b)) => a.name.cmp(&b.name),
-            (Directory(a), Directory(b)) => a.cmp(b),
+            (Directory(_), Directory(_)) => self.path().cmp(other.path()),
+            (Directory(_), _) => Ordering::Less,
+            (_, Directory(_)) => Ordering::Greater,
+            // Files, binary files, and symlinks are ordered together
+            _ => self.path().cmp(other.path()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_paths_are_walked_once() {
+        let paths = ["/r/ci/deep", "/r/src", "/r/ci", "/r/ci", "/r/cidr"].map(PathBuf::from);
+        assert_eq!(
+            outermost(paths.to_vec()),
+            ["/r/ci", "/r/cidr", "/r/src"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn binary_round_trips_as_base64() {
+        #[derive(Serialize, Deserialize)]
+        struct Wrapper {
+            contents: Vec<RecipeItem>,
         }
+
+        let bytes = vec![0x89, b'P', b'N', b'G', 0xff, 0x00];
+        let wrapper = Wrapper {
+            contents: vec![RecipeItem::Binary(Binary {
+                name: "logo.png".into(),
+                bytes: bytes.clone(),
+                mode: None,
+            })],
+        };
+
+        let toml = toml::to_string(&wrapper).unwrap();
+        assert!(toml.contains("binary = \"iVBOR/8A\""));
+
+        let back: Wrapper = toml::from_str(&toml).unwrap();
+        match &back.contents[0] {
+            RecipeItem::Binary(b) => assert_eq!(b.bytes, bytes),
+            other => panic!("expected a binary file, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn mode_is_octal_and_optional() {
+        let toml = "name = \"gradlew\"\ncontent = \"\"\nmode = \"755\"\n";
+        let file: File = toml::from_str(toml).unwrap();
+        assert_eq!(file.mode, Some(0o755));
+        assert_eq!(toml::to_string(&file).unwrap(), toml);
+
+        // A mode that is the usual default can still be given on purpose
+        let toml = "name = \"a\"\ncontent = \"\"\nmode = \"644\"\n";
+        let file: File = toml::from_str(toml).unwrap();
+        assert_eq!(file.mode, Some(DEFAULT_MODE));
+        assert_eq!(toml::to_string(&file).unwrap(), toml);
+
+        let old: File = toml::from_str("name = \"a\"\ncontent = \"\"").unwrap();
+        assert_eq!(old.mode, None);
+        assert!(!toml::to_string(&old).unwrap().contains("mode"));
+
+        assert!(toml::from_str::<File>("name = \"a\"\ncontent = \"\"\nmode = \"9\"").is_err());
+    }
+
+    #[test]
+    fn empty_dirs_are_left_out() {
+        let file = |name: &str| {
+            RecipeItem::File(File {
+                name: name.into(),
+                content: String::new(),
+                mode: None,
+            })
+        };
+        let contents = vec![
+            RecipeItem::Directory("ci".into()),
+            RecipeItem::Directory("ci/deep".into()),
+            RecipeItem::Directory("src".into()),
+            file("ci/deep/a.yml"),
+        ];
+
+        let kept: Vec<_> = without_empty_dirs(contents)
+            .iter()
+            .map(RecipeItem::name)
+            .collect();
+        assert_eq!(kept, ["ci", "ci/deep", "ci/deep/a.yml"]);
     }
 }
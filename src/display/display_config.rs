@@ -16,7 +16,7 @@ pub struct DisplayConfig {
     /// Default: None (determined by CLI only)
     pub show_descriptions: Option<bool>,
     /// How an individual recipe should be formatted.
-    /// Default: "{name} ({langs}){desc}"
+    /// Default: "{name} ({langs}){source}{desc}"
     #[serde(default = "default_recipe_fmt")]
     pub recipe_fmt: String,
 
@@ -36,6 +36,15 @@ pub struct DisplayConfig {
     #[serde(default = "default_desc_fmt")]
     pub desc_fmt: String,
 
+    // --- Source ---
+    /// Whether to show the directory that each recipe comes from.
+    /// Default: None (shown when recipes are read from more than one directory)
+    pub show_sources: Option<bool>,
+    /// How to format the directory that the recipe comes from.
+    /// Default: " [{source}]"
+    #[serde(default = "default_source_fmt")]
+    pub source_fmt: String,
+
     // --- Languages ---
     /// How each individual language should be formatted.
     /// Default: "{lang}"
@@ -60,6 +69,8 @@ impl Default for DisplayConfig {
             name_fmt: default_name_fmt(),
             name_bold: default_name_bold(),
             desc_fmt: default_desc_fmt(),
+            show_sources: None,
+            source_fmt: default_source_fmt(),
             lang_fmt: default_lang_fmt(),
             lang_colour: default_lang_colour(),
             langs_join: default_langs_join(),
@@ -71,13 +82,14 @@ use config_defaults::*;
 #[rustfmt::skip]
 mod config_defaults {
     //! Source of truth for `DisplayConfig::default` implementation
-    pub fn default_recipes_join()   -> String { "\n".to_string()                     }
-    pub fn default_recipes_suffix() -> String { "\n".to_string()                     }
-    pub fn default_recipe_fmt()     -> String { "{name} ({langs}){desc}".to_string() }
-    pub fn default_name_fmt()       -> String { "{name}".to_string()                 }
-    pub fn default_name_bold()      -> bool   { true                                 }
-    pub fn default_desc_fmt()       -> String { "\n  {desc}".to_string()             }
-    pub fn default_lang_fmt()       -> String { "{lang}".to_string()                 }
-    pub fn default_lang_colour()    -> bool   { true                                 }
-    pub fn default_langs_join()     -> String { " ".to_string()                      }
+    pub fn default_recipes_join()   -> String { "\n".to_string()                             }
+    pub fn default_recipes_suffix() -> String { "\n".to_string()                             }
+    pub fn default_recipe_fmt()     -> String { "{name} ({langs}){source}{desc}".to_string() }
+    pub fn default_name_fmt()       -> String { "{name}".to_string()                         }
+    pub fn default_name_bold()      -> bool   { true                                         }
+    pub fn default_desc_fmt()       -> String { "\n  {desc}".to_string()                     }
+    pub fn default_source_fmt()     -> String { " [{source}]".to_string()                    }
+    pub fn default_lang_fmt()       -> String { "{lang}".to_string()                         }
+    pub fn default_lang_colour()    -> bool   { true                                         }
+    pub fn default_langs_join()     -> String { " ".to_string()                              }
 }
@@ -28,6 +28,7 @@ pub fn display_recipes_with_config(recipes: &[&Recipe], config: &DisplayConfig)
 /// Formats a single recipe according to a provided configuration.
 fn cfg_display_recipe(recipe: &Recipe, config: &DisplayConfig) -> String {
     let show_description = config.show_descriptions.unwrap_or(true);
+    let show_source = config.show_sources.unwrap_or(false);
 
     let subs = HashMap::from([
         (
@@ -47,6 +48,10 @@ fn cfg_display_recipe(recipe: &Recipe, config: &DisplayConfig) -> String {
             "desc".to_string(),
             cfg_display_description(&recipe.description, &config.desc_fmt, show_description),
         ),
+        (
+            "source".to_string(),
+            cfg_display_source(recipe, &config.source_fmt, show_source),
+        ),
     ]);
 
     replace(subs, &config.recipe_fmt)
@@ -94,6 +99,16 @@ fn cfg_display_langs(
         .join(join_string)
 }
 
+/// Displays the directory a recipe comes from as configured.
+fn cfg_display_source(recipe: &Recipe, fmt_string: &str, show_source: bool) -> String {
+    let (true, Some(dir)) = (show_source, recipe.source_dir()) else {
+        return "".into();
+    };
+
+    let subs = HashMap::from([("source".to_string(), dir.display().to_string())]);
+    replace(subs, fmt_string)
+}
+
 /// Displays the recipe description as configured.
 fn cfg_display_description(description: &str, fmt_string: &str, show_desc: bool) -> String {
     if !show_desc {
@@ -28,10 +28,19 @@ fn build_recursive_content(files: &[RecipeItem]) -> Vec<TreeContent> {
     for file in files {
         match file {
             File(file) => {
-                root.insert(file.name.as_path(), true);
+                root.insert(file.name.as_path(), true, None);
+            }
+            // Binary files are shown with their size, since their contents aren't readable
+            Binary(file) => {
+                let size = format!(" ({})", file.display_size());
+                root.insert(&file.name, true, Some(&size));
+            }
+            Symlink(link) => {
+                let target = format!(" -> {}", link.target.display());
+                root.insert(&link.name, true, Some(&target));
             }
             Directory(name) => {
-                root.insert(name, false);
+                root.insert(name, false, None);
             }
         }
     }
@@ -125,12 +134,17 @@ impl TreeNode {
         }
     }
 
-    /// Insert a path into the tree by breaking it into its components.
-    fn insert(&mut self, path: &Path, is_file: bool) {
+    /// Insert a path into the tree by breaking it into its components. The note, if any, is
+    /// displayed after the last component.
+    fn insert(&mut self, path: &Path, is_file: bool, note: Option<&str>) {
         let mut current = self;
+        let mut components = path.components().peekable();
         // Break the path into its components, and at them one-by-one to the tree
-        for comp in path.components() {
-            let name = comp.as_os_str().to_string_lossy().into_owned();
+        while let Some(comp) = components.next() {
+            let mut name = comp.as_os_str().to_string_lossy().into_owned();
+            if let (None, Some(note)) = (components.peek(), note) {
+                name.push_str(note);
+            }
             current = current.children.entry(name).or_insert_with(TreeNode::new)
         }
         // Once we have reached the end of the recursion, mark it as a file if it is one.
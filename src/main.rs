@@ -1,5 +1,6 @@
 mod cli;
 mod config;
+mod conflict_strategy;
 mod content;
 mod display;
 mod hooks;
@@ -8,6 +9,9 @@ mod output_type;
 mod recipe;
 mod recipe_completer;
 mod replacer;
+mod symlink_strategy;
+#[cfg(test)]
+mod test_util;
 
 use cli::{Cli, Commands::*};
 use hooks::hooks;
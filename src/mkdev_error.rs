@@ -31,6 +31,37 @@ pub enum Error {
     #[error("failed to deserialise {0}: {1}")]
     Deserialisation(String, String),
 
+    /// Indicates that a recipe (or project config) wants to run commands that the user has not
+    /// approved.
+    #[error(
+        "the {0} wants to run commands that have not been approved:\n{cmds}\nUse --allow-commands to run them anyway.",
+        cmds = .1.join("\n")
+    )]
+    Untrusted(String, Vec<String>),
+
+    /// Indicates that a shell command was needed while shell commands are disabled.
+    #[error("shell commands are disabled by --no-shell, but these were needed:\n{}", .0.join("\n"))]
+    ShellDisabled(Vec<String>),
+
+    /// Indicates that a recipe can't be changed because it isn't in the recipe directory.
+    #[error("recipe `{0}` is read from {1}, which mkdev does not write to.")]
+    NotWritable(String, String),
+
+    /// Indicates that recipes are built on each other in a cycle.
+    #[error("recipes include or extend each other in a cycle: {}", .0.join(" -> "))]
+    Cycle(Vec<String>),
+
+    /// Indicates that recipes need things that the machine or the target directory don't have.
+    #[error("some requirements are not met:\n{}", .0.join("\n"))]
+    Unmet(Vec<String>),
+
+    /// Indicates that a `post_evoke` hook did not succeed.
+    #[error("post-evoke hook `{0}` failed {status}", status = match .1 {
+        Some(code) => format!("with exit code {code}."),
+        None => String::from("without an exit code (it was killed by a signal)."),
+    })]
+    HookFailed(String, Option<i32>),
+
     /// Indicates that an action would be destructive.
     #[error("'{0}' already exists. Use -s to overwrite.")]
     DestructionWarning(String),
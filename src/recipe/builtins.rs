@@ -0,0 +1,197 @@
+//! Built-in substitution functions.
+//!
+//! Substitutions prefixed with `mk::` are evaluated in-process rather than by spawning a shell,
+//! so they are fast and work even where common command line tools are missing. Functions that
+//! take an argument are called like `mk::date(%Y)`; the rest are called like `mk::user`.
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use chrono::Local;
+
+/// The names of the built-in functions.
+const NAMES: &[&str] = &[
+    "date",
+    "env",
+    "user",
+    "hostname",
+    "git_user_name",
+    "git_user_email",
+    "uuid",
+    "cwd_basename",
+];
+
+/// Whether a call (without its `mk::` prefix) names a built-in function, however it is called.
+///
+/// Values that only happen to start with `mk::` are older than built-ins, and are left as they are.
+pub fn is_builtin(call: &str) -> bool {
+    let name = call.split_once('(').map_or(call, |(name, _)| name);
+    NAMES.contains(&name)
+}
+
+/// Evaluates a built-in function call (without its `mk::` prefix).
+///
+/// Returns `None` if the function does not exist, is called with the wrong arguments, or has no
+/// value on this machine.
+pub fn builtin(call: &str) -> Option<String> {
+    let (name, arg) = match call.split_once('(') {
+        Some((name, arg)) => (name, Some(arg.strip_suffix(')')?)),
+        None => (call, None),
+    };
+
+    match (name, arg) {
+        ("date", Some(fmt)) => date(fmt),
+        ("env", Some(var)) => env::var(var).ok(),
+        ("user", None) => Some(whoami::username()),
+        ("hostname", None) => whoami::fallible::hostname().ok(),
+        ("git_user_name", None) => git_config("user", "name"),
+        ("git_user_email", None) => git_config("user", "email"),
+        ("uuid", None) => Some(uuid_v4()),
+        ("cwd_basename", None) => env::current_dir()
+            .ok()?
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned()),
+        _ => None,
+    }
+}
+
+/// Formats the current local time with `strftime`-style specifiers, like `date +FMT`.
+fn date(fmt: &str) -> Option<String> {
+    let mut out = String::new();
+    // Invalid specifiers are reported as a formatting error, rather than a panic
+    write!(out, "{}", Local::now().format(fmt)).ok()?;
+
+    Some(out)
+}
+
+/// Reads a value from the user's git configuration without running git.
+///
+/// Files are read in the same order git reads them, so later files take precedence: the XDG
+/// config, `~/.gitconfig`, and then the config of the repository containing the cwd (if any).
+/// `include` directives are not followed.
+fn git_config(section: &str, key: &str) -> Option<String> {
+    let mut files: Vec<PathBuf> = vec![];
+
+    let xdg = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".config")));
+    files.extend(xdg.map(|dir| dir.join("git").join("config")));
+    files.extend(dirs::home_dir().map(|home| home.join(".gitconfig")));
+
+    let cwd = env::current_dir().ok()?;
+    if let Some(repo) = cwd.ancestors().find(|dir| dir.join(".git").is_dir()) {
+        files.push(repo.join(".git").join("config"));
+    }
+
+    files
+        .iter()
+        .filter_map(|file| fs::read_to_string(file).ok())
+        .filter_map(|contents| ini_value(&contents, section, key))
+        .next_back()
+}
+
+/// Finds the last value of `key` in `[section]` of a git-style ini file.
+fn ini_value(contents: &str, section: &str, key: &str) -> Option<String> {
+    let mut in_section = false;
+    let mut value = None;
+
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let name = header.trim_end_matches(']').trim();
+            in_section = name.eq_ignore_ascii_case(section);
+            continue;
+        }
+
+        if let (true, Some((k, v))) = (in_section, line.split_once('='))
+            && k.trim().eq_ignore_ascii_case(key)
+        {
+            value = Some(v.trim().trim_matches('"').to_string());
+        }
+    }
+
+    value
+}
+
+/// Generates a random (version 4) UUID.
+///
+/// The randomness comes from std's randomly keyed hasher, which is plenty for identifiers but
+/// is not suitable for anything security sensitive.
+fn uuid_v4() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos());
+
+    let mut bytes = [0u8; 16];
+    for chunk in bytes.chunks_mut(8) {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(nanos);
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+
+    // Set the version (4) and variant (RFC 4122) bits
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn date_formats_like_strftime() {
+        let year = builtin("date(%Y)").unwrap();
+        assert_eq!(year.len(), 4);
+        assert!(year.chars().all(|c| c.is_ascii_digit()));
+    }
+
+    #[test]
+    fn unknown_or_malformed_calls_fail() {
+        assert_eq!(builtin("nope"), None);
+        assert_eq!(builtin("date"), None);
+        assert_eq!(builtin("user(x)"), None);
+        assert_eq!(builtin("env(HOME"), None);
+    }
+
+    #[test]
+    fn only_known_names_are_builtins() {
+        assert!(is_builtin("date(%Y)"));
+        assert!(is_builtin("date"));
+        assert!(!is_builtin("nope"));
+        assert!(!is_builtin("Some literal text"));
+    }
+
+    #[test]
+    fn uuid_is_version_4() {
+        let uuid = uuid_v4();
+        assert_eq!(uuid.len(), 36);
+        assert_eq!(&uuid[14..15], "4");
+        assert_ne!(uuid, uuid_v4());
+    }
+
+    #[test]
+    fn ini_value_reads_last_match_in_section() {
+        let ini =
+            "[core]\n\tname = no\n[user]\n\tname = \"A B\"\n\temail = a@b\n[User]\nname = C\n";
+        assert_eq!(ini_value(ini, "user", "name").unwrap(), "C");
+        assert_eq!(ini_value(ini, "user", "email").unwrap(), "a@b");
+        assert_eq!(ini_value(ini, "core", "email"), None);
+    }
+}
@@ -0,0 +1,154 @@
+//! Imprinting from a git revision rather than the working tree.
+//!
+//! The tree at the revision is extracted (with `git archive`) into a temporary directory, which is
+//! then imprinted like any other directory. Only tracked files are in the archive, so nothing
+//! untracked can end up in the recipe.
+use crate::mkdev_error::{
+    Error::{self, *},
+    ResultExt,
+};
+
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output, Stdio};
+use std::thread;
+
+/// A temporary copy of a directory as it was at a git revision. It is removed when dropped.
+#[derive(Debug)]
+pub struct Checkout {
+    /// Where the copy was extracted to.
+    pub dir: PathBuf,
+}
+
+impl Checkout {
+    /// Extracts `root`, a directory in a git repository, as it was at `rev`.
+    pub fn new(root: &Path, rev: &str) -> Result<Self, Error> {
+        // Archive the root's subtree from the top of the repository, so that paths stay relative
+        // to the root
+        let location = git(
+            root,
+            &["rev-parse", "--show-toplevel", "--show-prefix"],
+            rev,
+        )?;
+        let location = String::from_utf8_lossy(&location.stdout).into_owned();
+        let mut lines = location.lines();
+        let toplevel = lines.next().unwrap_or_default();
+        let tree = format!("{rev}:{}", lines.next().unwrap_or_default());
+
+        // Removes the directory again if extracting fails
+        let dir = temp_dir()?;
+        let checkout = Self {
+            dir: dir
+                .canonicalize()
+                .context("unable to find temporary directory")?,
+        };
+
+        let mut archive = Command::new("git")
+            .arg("-C")
+            .arg(toplevel)
+            // git only tracks whether a file is executable, so the modes are 644 or 755
+            .args(["-c", "tar.umask=022", "archive", "--format=tar", &tree])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("unable to run git")?;
+        let stdout = archive.stdout.take().expect("stdout is piped");
+        // Read while tar runs, so that git never blocks on a full pipe
+        let mut stderr = archive.stderr.take().expect("stderr is piped");
+        let errors = thread::spawn(move || {
+            let mut errors = vec![];
+            _ = stderr.read_to_end(&mut errors);
+            errors
+        });
+
+        let extracted = Command::new("tar")
+            .arg("-x")
+            .arg("-C")
+            .arg(&checkout.dir)
+            .stdin(stdout)
+            .output();
+        let status = archive.wait().context("unable to run git")?;
+        let archived = Output {
+            status,
+            stdout: vec![],
+            stderr: errors.join().unwrap_or_default(),
+        };
+
+        let extracted = match extracted {
+            Err(why) if why.kind() == io::ErrorKind::NotFound => {
+                return Err(Io(
+                    "unable to run tar".into(),
+                    "tar is needed to imprint from a git revision, but it is not on PATH".into(),
+                ));
+            }
+            extracted => extracted.context("unable to run tar")?,
+        };
+        check(archived, rev)?;
+        check(extracted, rev)?;
+
+        Ok(checkout)
+    }
+}
+
+impl Drop for Checkout {
+    fn drop(&mut self) {
+        _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Creates a new, empty directory with a random name in the system's temporary directory.
+///
+/// The directory is always new, so it can't be a link (or anything else) that was already there,
+/// and only the user can get into it.
+fn temp_dir() -> Result<PathBuf, Error> {
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+
+    for _ in 0..8 {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(process::id());
+        let dir = env::temp_dir().join(format!("mkdev-imprint-{:016x}", hasher.finish()));
+
+        match builder.create(&dir) {
+            Err(why) if why.kind() == io::ErrorKind::AlreadyExists => continue,
+            created => {
+                return created
+                    .map(|_| dir)
+                    .context("unable to create temporary directory");
+            }
+        }
+    }
+
+    Err(Io(
+        "unable to create temporary directory".into(),
+        "every name that was tried is taken".into(),
+    ))
+}
+
+/// Runs git in `dir`, failing if it doesn't succeed.
+fn git(dir: &Path, args: &[&str], rev: &str) -> Result<Output, Error> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .context("unable to run git")?;
+
+    check(output, rev)
+}
+
+/// Turns the output of a command that failed into an error that explains why.
+fn check(output: Output, rev: &str) -> Result<Output, Error> {
+    match output.status.success() {
+        true => Ok(output),
+        false => Err(Io(
+            format!("unable to read `{rev}` from git"),
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        )),
+    }
+}
@@ -0,0 +1,338 @@
+//! Resolves recipes that are built from other recipes, through `extends` and `includes`, into
+//! the effective recipe that is listed and evoked.
+//!
+//! The contents of the effective recipe are built up in order, and an item replaces any earlier
+//! item with the same path:
+//!
+//! 1. the base recipe's contents, without the paths listed in `remove` (or anything under them),
+//! 2. the contents of each included recipe, in the order they are listed,
+//! 3. the recipe's own contents.
+//!
+//! Then `patches` are applied to the resulting files. Substitutions are layered in the same order,
+//! so the recipe's own take precedence, and `post_evoke` hooks run in the same order. A variable
+//! is asked for once, using the first definition from the recipe, its includes, and then its
+//! base, and the recipe needs everything that any of them `requires`.
+use super::Recipe;
+use crate::content::RecipeItem;
+use crate::mkdev_error::Error::{self, *};
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+impl Recipe {
+    /// Resolves the recipe against recipes that are already resolved, such as the ones from
+    /// `Recipe::gather`.
+    ///
+    /// Fails if the recipe, or one it is built on, was already found to be unresolvable.
+    pub fn resolve(&self, recipes: &HashMap<String, Recipe>) -> Result<Recipe, Error> {
+        if let Some(why) = &self.unresolved {
+            return Err(why.clone());
+        }
+
+        self.compose(&mut |name| match recipes.get(name) {
+            Some(recipe) => match &recipe.unresolved {
+                Some(why) => Err(why.clone()),
+                None => Ok(recipe.clone()),
+            },
+            None => Err(Invalid("recipe".into(), Some(vec![name.to_string()]))),
+        })
+    }
+
+    /// Builds the effective recipe, using `lookup` to get the effective recipes it is built on.
+    fn compose(
+        &self,
+        lookup: &mut dyn FnMut(&str) -> Result<Recipe, Error>,
+    ) -> Result<Recipe, Error> {
+        if self.extends.is_none() && self.includes.is_empty() && self.patches.is_empty() {
+            return Ok(self.clone());
+        }
+
+        let base = self.extends.as_deref().map(&mut *lookup).transpose()?;
+        let mut layers = vec![];
+        for include in &self.includes {
+            layers.push((include, lookup(include.recipe())?));
+        }
+
+        let mut contents: BTreeMap<PathBuf, RecipeItem> = BTreeMap::new();
+        let mut subs = BTreeMap::new();
+        let mut variables = self.variables.clone();
+        let mut languages = self.languages.clone();
+        let mut post_evoke = vec![];
+        let mut requires = self.requires.clone();
+        let mut add_layer = |recipe: &Recipe| {
+            subs.extend(recipe.subs.clone());
+            requires.merge(&recipe.requires);
+            post_evoke.extend(recipe.post_evoke.iter().cloned());
+            for language in &recipe.languages {
+                if !languages.iter().any(|l| l.name == language.name) {
+                    languages.push(language.clone());
+                }
+            }
+        };
+
+        if let Some(base) = &base {
+            add_layer(base);
+            let kept = base
+                .contents
+                .iter()
+                .filter(|item| !self.remove.iter().any(|r| item.path().starts_with(r)));
+            for item in kept {
+                contents.insert(item.path().to_path_buf(), item.clone());
+            }
+        }
+
+        for (include, included) in layers {
+            add_layer(&included);
+            variables.extend(included.variables.iter().cloned());
+            if let Some(into) = include.into() {
+                contents.insert(
+                    into.to_path_buf(),
+                    RecipeItem::Directory(into.to_path_buf()),
+                );
+            }
+            for item in included.contents {
+                let item = match include.into() {
+                    Some(into) => item.mounted(into),
+                    None => item,
+                };
+                contents.insert(item.path().to_path_buf(), item);
+            }
+        }
+
+        for item in &self.contents {
+            contents.insert(item.path().to_path_buf(), item.clone());
+        }
+        // Unlike everything else, the base's variables come after the includes
+        if let Some(base) = &base {
+            variables.extend(base.variables.iter().cloned());
+        }
+        subs.extend(self.subs.clone());
+        post_evoke.extend(self.post_evoke.iter().cloned());
+
+        for patch in &self.patches {
+            let invalid = |why: String| {
+                let what = format!("patch for `{}` in `{}`", patch.path.display(), self.name);
+                Invalid(what, Some(vec![why]))
+            };
+
+            let Some(RecipeItem::File(file)) = contents.get_mut(&patch.path) else {
+                return Err(invalid("there is no text file at that path".into()));
+            };
+            file.content = patch.apply(&file.content).map_err(invalid)?;
+        }
+
+        let mut contents: Vec<_> = contents.into_values().collect();
+        contents.sort();
+
+        let description = match (self.description.is_empty(), &base) {
+            (true, Some(base)) => base.description.clone(),
+            _ => self.description.clone(),
+        };
+
+        Ok(Recipe {
+            description,
+            languages,
+            extends: None,
+            remove: vec![],
+            patches: vec![],
+            includes: vec![],
+            post_evoke,
+            requires,
+            subs,
+            variables,
+            contents,
+            ..self.clone()
+        })
+    }
+}
+
+/// Resolves every recipe into its effective recipe.
+///
+/// A recipe that can't be resolved (e.g. it is built on a recipe that doesn't exist) is kept as it
+/// is written, with the reason in `unresolved`, which is only reported if it is evoked.
+pub fn resolve_all(raw: HashMap<String, Recipe>) -> HashMap<String, Recipe> {
+    let mut resolved = HashMap::new();
+    let mut failed = vec![];
+
+    for name in raw.keys() {
+        if let Err(why) = resolve_one(name, &raw, &mut resolved, &mut vec![]) {
+            failed.push((name, why));
+        }
+    }
+
+    // Added at the end, so that no other recipe is resolved against them
+    for (name, why) in failed {
+        let recipe = Recipe {
+            unresolved: Some(why),
+            ..raw[name].clone()
+        };
+        resolved.insert(name.clone(), recipe);
+    }
+
+    resolved
+}
+
+/// Recursive helper for `resolve_all`; `stack` holds the chain of recipes being resolved.
+fn resolve_one(
+    name: &str,
+    raw: &HashMap<String, Recipe>,
+    resolved: &mut HashMap<String, Recipe>,
+    stack: &mut Vec<String>,
+) -> Result<Recipe, Error> {
+    if let Some(recipe) = resolved.get(name) {
+        return Ok(recipe.clone());
+    }
+
+    stack.push(name.to_string());
+    if stack[..stack.len() - 1].iter().any(|r| r == name) {
+        return Err(Cycle(stack.clone()));
+    }
+
+    let recipe = raw
+        .get(name)
+        .ok_or_else(|| Invalid("recipe".into(), Some(vec![name.to_string()])))?;
+    let out = recipe.compose(&mut |dep| resolve_one(dep, raw, resolved, stack))?;
+
+    stack.pop();
+    resolved.insert(name.to_string(), out.clone());
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Include, Patch, Variable};
+    use super::*;
+    use crate::test_util::recipe;
+
+    fn resolve(list: Vec<Recipe>) -> HashMap<String, Recipe> {
+        resolve_all(list.into_iter().map(|r| (r.name.clone(), r)).collect())
+    }
+
+    fn content(recipe: &Recipe, path: &str) -> Option<String> {
+        recipe.contents.iter().find_map(|item| match item {
+            RecipeItem::File(file) if file.name.as_os_str() == path => Some(file.content.clone()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn includes_are_layered_in_order() {
+        let mut lib = recipe("lib", &[("README", "lib")]);
+        lib.includes = vec![
+            Include::Recipe("license".into()),
+            Include::Mounted {
+                recipe: "ci".into(),
+                into: ".github".into(),
+            },
+        ];
+        let resolved = resolve(vec![
+            lib,
+            recipe("license", &[("LICENSE", "MIT")]),
+            recipe("ci", &[("ci.yml", "ci"), ("LICENSE", "CI")]),
+        ]);
+
+        let names: Vec<_> = resolved["lib"].contents.iter().map(|c| c.name()).collect();
+        assert_eq!(
+            names,
+            [
+                ".github",
+                ".github/LICENSE",
+                ".github/ci.yml",
+                "LICENSE",
+                "README"
+            ]
+        );
+    }
+
+    #[test]
+    fn extends_with_overrides_removals_and_patches() {
+        let mut base = recipe("base", &[("README", "base\n"), ("ci/a.yml", "a")]);
+        base.includes = vec![Include::Recipe("ignore".into())];
+        base.post_evoke = vec!["git init".into()];
+
+        let mut child = recipe("child", &[("LICENSE", "GPL\n")]);
+        child.extends = Some("base".into());
+        child.remove = vec!["ci".into()];
+        child.post_evoke = vec!["cargo fetch".into()];
+        child.patches = vec![
+            toml::from_str("path = \"README\"\ndiff = \"@@ -1 +1 @@\\n-base\\n+child\\n\"")
+                .unwrap(),
+            toml::from_str::<Patch>("path = \".gitignore\"\nappend = \"*.log\\n\"").unwrap(),
+        ];
+
+        let resolved = resolve(vec![
+            base,
+            child,
+            recipe("ignore", &[(".gitignore", "target\n")]),
+        ]);
+        let child = &resolved["child"];
+
+        assert_eq!(content(child, "README").unwrap(), "child\n");
+        assert_eq!(content(child, "LICENSE").unwrap(), "GPL\n");
+        assert_eq!(content(child, ".gitignore").unwrap(), "target\n*.log\n");
+        assert_eq!(content(child, "ci/a.yml"), None);
+        assert_eq!(child.post_evoke, ["git init", "cargo fetch"]);
+        assert!(child.extends.is_none() && child.includes.is_empty());
+    }
+
+    #[test]
+    fn includes_define_variables_before_the_base() {
+        let variable = |default: &str| {
+            toml::from_str::<Variable>(&format!("name = \"license\"\ndefault = \"{default}\""))
+                .unwrap()
+        };
+        let mut base = recipe("base", &[]);
+        base.variables = vec![variable("MIT")];
+        let mut license = recipe("license", &[]);
+        license.variables = vec![variable("GPL")];
+
+        let mut child = recipe("child", &[]);
+        child.extends = Some("base".into());
+        child.includes = vec![Include::Recipe("license".into())];
+        let resolved = resolve(vec![base, license, child]);
+
+        let defaults: Vec<_> = resolved["child"]
+            .variables
+            .iter()
+            .map(|v| v.default.as_deref().unwrap())
+            .collect();
+        assert_eq!(defaults, ["GPL", "MIT"]);
+    }
+
+    #[test]
+    fn unresolvable_recipes_are_kept_as_written() {
+        let mut a = recipe("a", &[]);
+        a.extends = Some("b".into());
+        let mut b = recipe("b", &[]);
+        b.includes = vec![Include::Recipe("a".into())];
+        let mut bad = recipe("bad", &[]);
+        bad.patches = vec![toml::from_str("path = \"x\"\nappend = \"x\"").unwrap()];
+        let mut missing = recipe("missing", &[]);
+        missing.extends = Some("nope".into());
+
+        let resolved = resolve(vec![a, b, bad, missing, recipe("ok", &[])]);
+        let mut unresolved: Vec<_> = resolved
+            .values()
+            .filter(|r| r.unresolved.is_some())
+            .map(|r| r.name.as_str())
+            .collect();
+        unresolved.sort();
+        assert_eq!(unresolved, ["a", "b", "bad", "missing"]);
+        assert_eq!(resolved["missing"].extends.as_deref(), Some("nope"));
+
+        // They only fail when they are used
+        assert!(resolved["missing"].resolve(&resolved).is_err());
+        let mut user = recipe("user", &[]);
+        user.extends = Some("missing".into());
+        assert!(user.resolve(&resolved).is_err());
+        assert!(resolved["ok"].resolve(&resolved).is_ok());
+
+        let mut a = recipe("a", &[]);
+        a.extends = Some("a".into());
+        let raw = HashMap::from([("a".to_string(), a)]);
+        match resolve_one("a", &raw, &mut HashMap::new(), &mut vec![]) {
+            Err(Cycle(chain)) => assert_eq!(chain, ["a", "a"]),
+            other => panic!("expected a cycle, got {other:?}"),
+        }
+    }
+}
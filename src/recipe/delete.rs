@@ -1,7 +1,7 @@
 //! Implementation of `mk delete`.
 //!
 //! Used to delete recipes from their default location.
-use super::{Recipe, recipe_dir};
+use super::{Recipe, recipe_dir, writable_source};
 use crate::cli::Delete;
 use crate::mkdev_error::{
     Error::{self, *},
@@ -10,36 +10,76 @@ use crate::mkdev_error::{
 
 use std::collections::HashMap;
 use std::fs;
-use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// Deletes a recipe based on command line arguments.
 pub fn delete_recipe(args: Delete, user_recipes: HashMap<String, Recipe>) -> Result<(), Error> {
-    let to_delete = user_recipes.get(args.recipe.as_str());
+    let dir = recipe_dir().context("unable to locate recipe directory")?;
+    let deleted_file = delete_from(&dir, &args.recipe, &user_recipes)?;
 
-    match to_delete {
-        Some(recipe) => {
-            let deleted_file = recipe
-                .delete()
-                .context(&format!("unable to delete `{}`", recipe.name))?;
+    println!("Deleted recipe at {}.", &deleted_file.display());
 
-            println!("Deleted recipe at {}.", &deleted_file.display());
+    Ok(())
+}
+
+/// Deletes the recipe called `name` from `dir`, the recipe directory.
+///
+/// A recipe of the same name elsewhere on the recipe path is never deleted, even if it takes
+/// precedence over the one in `dir`.
+fn delete_from(
+    dir: &Path,
+    name: &str,
+    user_recipes: &HashMap<String, Recipe>,
+) -> Result<PathBuf, Error> {
+    let path = writable_source(dir, name, user_recipes);
+    if path.is_file() {
+        fs::remove_file(&path).context(&format!("unable to delete `{name}`"))?;
+        return Ok(path);
+    }
 
-            Ok(())
-        }
-        None => Err(Invalid("recipe".into(), Some(vec![args.recipe]))),
+    match user_recipes.get(name) {
+        Some(recipe) => Err(NotWritable(
+            recipe.name.clone(),
+            recipe
+                .source_dir()
+                .unwrap_or(Path::new(""))
+                .display()
+                .to_string(),
+        )),
+        None => Err(Invalid("recipe".into(), Some(vec![name.to_string()]))),
     }
 }
 
-impl Recipe {
-    /// Delete the recipe by deleting its serialised self
-    pub fn delete(&self) -> io::Result<PathBuf> {
-        let mut data_dir = recipe_dir()?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{TestDir, recipe};
+
+    #[test]
+    fn shadowed_recipes_are_deleted_from_the_recipe_dir() {
+        let root = TestDir::new("delete");
+        let (writable, project) = (root.join("recipes"), root.join("project"));
+        fs::create_dir_all(&writable).unwrap();
+        fs::create_dir_all(&project).unwrap();
+        fs::write(writable.join("foo.toml"), "").unwrap();
+        fs::write(project.join("foo.toml"), "").unwrap();
 
-        data_dir.push(format!("{}.toml", self.name));
+        // The project's `foo` takes precedence over the user's
+        let mut shadowing: Recipe = recipe("foo", &[]);
+        shadowing.source = Some(project.join("foo.toml"));
+        let recipes = HashMap::from([("foo".to_string(), shadowing)]);
 
-        fs::remove_file(&data_dir)?;
+        assert_eq!(
+            delete_from(&writable, "foo", &recipes).unwrap(),
+            writable.join("foo.toml")
+        );
+        assert!(project.join("foo.toml").exists());
 
-        Ok(data_dir)
+        let again = delete_from(&writable, "foo", &recipes);
+        assert!(matches!(again, Err(NotWritable(..))));
+        assert!(matches!(
+            delete_from(&writable, "bar", &recipes),
+            Err(Invalid(..))
+        ));
     }
 }
@@ -4,9 +4,16 @@
 //! are systematically loaded, formatted with custom substitutions, and copied into the target
 //! directory.
 use super::Recipe;
+use super::builtins::{builtin, is_builtin};
+use super::manifest::Manifest;
+use super::transaction::Transaction;
+use super::trust::TrustStore;
+use super::variables::{parse_key_values, resolve_variables};
+use super::version::deserialise_recipe;
 
 use crate::cli::Evoke;
-use crate::config::Config;
+use crate::config::{Config, default_subs};
+use crate::conflict_strategy::ConflictStrategy;
 use crate::content::RecipeItem;
 use crate::mkdev_error::{
     Error::{self, *},
@@ -15,37 +22,25 @@ use crate::mkdev_error::{
 use crate::replacer::{InvalidTokenStrategy, ReplaceFmt};
 use crate::warning;
 
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::env::current_dir;
 use std::fs;
-use std::io;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
+use colored::Colorize;
+use diffy::PatchFormatter;
+
 /// Evokes a recipe according to arguments from the command line.
 pub fn build_recipes(args: Evoke, user_recipes: HashMap<String, Recipe>) -> Result<(), Error> {
     // --- Error handling ---
-    // There is an error if no recipes are provided
-    if args.recipes.is_empty() {
-        return Err(NoneSpecified("recipes".into()));
-    }
-
-    let non_existant_recipes: Vec<String> = args
-        .recipes
+    // Installed recipes are already resolved, but ones from a file or stdin may build on them
+    let recipes = select_recipes(&args, &user_recipes)?
         .iter()
-        .filter_map(|r| match user_recipes.contains_key(r) {
-            false => {
-                let r = r.to_string();
-                Some(r)
-            }
-            true => None,
-        })
-        .collect();
-
-    // There is an error if there are any non-existent recipes specified by the user
-    if !non_existant_recipes.is_empty() {
-        return Err(Invalid("recipe(s)".into(), Some(non_existant_recipes)));
-    }
+        .map(|r| r.resolve(&user_recipes))
+        .collect::<Result<Vec<_>, _>>()?;
 
     // --- Replacer setup ---
     // Ensure project name is set to something
@@ -59,108 +54,818 @@ pub fn build_recipes(args: Evoke, user_recipes: HashMap<String, Recipe>) -> Resu
         None => current_dir().context("unable to get cwd")?,
     };
 
-    let user_subs: HashMap<_, _> = Config::get()?
-        .subs
+    // Requirements are checked before anything is asked for or written, and every unmet one is
+    // reported at once
+    let unmet: Vec<_> = recipes
+        .iter()
+        .flat_map(|r| {
+            let needs = move |what| format!("`{}` needs {what}", r.name);
+            r.requires.unmet(&dir).into_iter().map(needs)
+        })
+        .collect();
+    if !unmet.is_empty() {
+        return Err(Unmet(unmet));
+    }
+
+    // Patch in reserved values
+    let patch_reserved = |(k, v): (&String, &String)| match v.as_str() {
+        "mk::name" => (k.clone(), literal(&name)),
+        #[rustfmt::skip]
+        "mk::dir" => (k.clone(), literal(&dir.to_string_lossy())),
+        _ => (k.clone(), v.clone()),
+    };
+
+    // Recipe variables and `--set` values are literals, so they are passed through like reserved
+    // values
+    let sets = parse_key_values(&args.set, "--set")?;
+    let variables = recipes.iter().flat_map(|r| &r.variables);
+    let values = resolve_variables(variables, &sets)?;
+    let literals: Vec<_> = values
+        .into_iter()
+        .chain(sets)
+        .map(|(k, v)| (k, literal(&v)))
+        .collect();
+
+    let config = Config::get()?;
+    let user_subs: HashMap<_, _> = default_subs()
+        .into_iter()
+        .chain(config.subs.clone())
+        .collect();
+
+    // --- Plan ---
+    // Every substitution is resolved and every collision is found before anything is written.
+    let mut manifest = Manifest::load(&dir)?;
+    let mut trust = TrustStore::load()?;
+    let approving = !args.no_shell && !args.allow_commands;
+    // A project config comes with the project, so its commands are approved like a recipe's
+    if let (true, Some(file)) = (approving, &config.project_file) {
+        trust.ensure_project_trusted(file, &config.project_subs)?;
+    }
+    let resolver = Resolver {
+        no_shell: args.no_shell,
+        ..Default::default()
+    };
+    let mut plan = vec![];
+    let mut hooks = vec![];
+    for recipe in &recipes {
+        // Commands that come with a recipe need the user's approval before they are run, but
+        // hooks that are skipped are never run
+        if approving && args.no_hooks {
+            trust.ensure_trusted(&Recipe {
+                post_evoke: vec![],
+                ..recipe.clone()
+            })?;
+        } else if approving {
+            trust.ensure_trusted(recipe)?;
+        }
+
+        // Later layers take precedence: defaults < user config < recipe < command line
+        let subs: HashMap<_, _> = user_subs
+            .iter()
+            .chain(&recipe.subs)
+            .map(patch_reserved)
+            .chain(literals.iter().cloned())
+            .collect();
+        let re = ReplaceFmt::new(subs, ("{{", "}}"), InvalidTokenStrategy::Preserve);
+
+        plan.extend(build_plan(&dir, &recipe.contents, &re, &resolver));
+        if !args.no_hooks {
+            let render = |hook: &String| Hook::render(hook, &re, &resolver);
+            hooks.extend(recipe.post_evoke.iter().map(render));
+        }
+        manifest.record(recipe);
+    }
+
+    // Hooks always run in a shell
+    let mut blocked = resolver.blocked.take();
+    if args.no_shell {
+        blocked.extend(hooks.iter().map(Hook::display));
+    }
+    if !blocked.is_empty() {
+        return Err(ShellDisabled(blocked));
+    }
+
+    mark_conflicts(&mut plan);
+    check_links(&plan, &dir, args.allow_external_links)?;
+
+    // The recipe output (rather than what actually gets written) is the base for the next merge
+    let outputs: Vec<_> = plan
         .iter()
-        // Patch in reserved values
-        .map(|(k, v)| match v.as_str() {
-            "mk::name" => (k.clone(), format!("mk::{}", name.clone())),
-            #[rustfmt::skip]
-            "mk::dir" => (k.clone(), format!("mk::{}", dir.to_string_lossy())),
-            _ => (k.clone(), v.clone()),
+        .filter_map(|planned| match &planned.operation {
+            Operation::WriteFile(content, _) => Some((planned.path.clone(), content.clone())),
+            _ => None,
         })
         .collect();
 
-    let re = ReplaceFmt::new(user_subs, ("{{", "}}"), InvalidTokenStrategy::Preserve);
+    if args.update {
+        merge_tracked(&mut plan, &dir, &manifest);
+    }
+
+    // -s is shorthand for overwriting, but an explicit strategy always wins
+    let strategy = match (args.on_conflict, args.suppress_warnings) {
+        (Some(strategy), _) => strategy,
+        (None, true) => ConflictStrategy::Overwrite,
+        (None, false) => ConflictStrategy::Abort,
+    };
+
+    if args.dry_run {
+        report_plan(&plan, strategy);
+        report("write", &Manifest::path(&dir), None);
+        for hook in &hooks {
+            println!("{} $ {}", format!("{:<9}", "run").green(), hook.display());
+        }
+        return Ok(());
+    }
+
+    let plan = resolve_conflicts(plan, strategy)?;
+
+    // A skipped file keeps whatever the user had, so the recipe output is no base for merging it
+    let written = |path: &PathBuf| plan.iter().any(|planned| &planned.path == path);
+    for (path, content) in outputs.into_iter().filter(|(path, _)| written(path)) {
+        let relative = path.strip_prefix(&dir).unwrap_or(&path).to_path_buf();
+        manifest.files.insert(relative, content);
+    }
 
     // --- Build ---
-    let extra_args = args.clone();
-    args.recipes.iter().try_for_each(|r| {
-        let recipe = user_recipes
-            .get(r)
-            .expect("Invalid recipes should have been filtered out.");
+    let mut tx = Transaction::new();
+    let built = plan
+        .iter()
+        .try_for_each(|planned| apply(planned, &mut tx, args.verbose))
+        .and_then(|_| tx.write(&Manifest::path(&dir), manifest.to_toml()));
+
+    if let Err(why) = built {
+        if let Err(rollback_err) = tx.rollback() {
+            warning!("unable to fully roll back changes: {rollback_err}");
+        }
 
         // Context for failure, should building fail
-        let context = format!("unable to write `{}` to `{}`", recipe.name, dir.display());
-        build(&dir, &recipe.contents, &extra_args, &re).context(&context)
-    })
+        let names: Vec<_> = recipes.iter().map(|r| r.name.as_str()).collect();
+        let context = format!(
+            "unable to write `{}` to `{}`",
+            names.join("`, `"),
+            dir.display()
+        );
+        return Err(why).context(&context);
+    }
+
+    // --- Hooks ---
+    for hook in &hooks {
+        run_hook(hook, &dir, args.verbose)?;
+    }
+
+    Ok(())
 }
 
-/// Builds a single recipe by taking in its contents and instantiating it recursively
-fn build(
+/// A `post_evoke` hook with its substitutions resolved.
+#[derive(Debug)]
+struct Hook {
+    /// The command, which refers to each substituted value by the variable it is passed in.
+    cmd: String,
+    /// The substituted values, which are passed to the shell as environment variables rather
+    /// than pasted into the command, so that a value can't change what the command does.
+    values: Vec<(String, String)>,
+}
+
+impl Hook {
+    /// Resolves the substitutions in a hook from a recipe.
+    ///
+    /// Each value is referred to as `${MKDEV_VALUE_<n>}`, which the shell expands to the value as
+    /// it is, inside double quotes or not.
+    fn render(hook: &str, re: &ReplaceFmt, resolver: &Resolver) -> Self {
+        let values = RefCell::new(vec![]);
+        let cmd = re.replace_escaped(
+            hook,
+            |value| resolver.resolve(value),
+            |value| {
+                let mut values = values.borrow_mut();
+                let var = format!("MKDEV_VALUE_{}", values.len());
+                let reference = format!("${{{var}}}");
+                values.push((var, value));
+                reference
+            },
+        );
+
+        Self {
+            cmd,
+            values: values.into_inner(),
+        }
+    }
+
+    /// The command with the values in place, as it is shown to the user.
+    fn display(&self) -> String {
+        self.values
+            .iter()
+            .fold(self.cmd.clone(), |cmd, (var, value)| {
+                cmd.replace(&format!("${{{var}}}"), value)
+            })
+    }
+}
+
+/// Runs a `post_evoke` hook in a shell in the target directory.
+///
+/// The hook's output is passed through to the user. Fails if the hook doesn't succeed.
+fn run_hook(hook: &Hook, dir: &Path, verbose: bool) -> Result<(), Error> {
+    let shown = hook.display();
+    if verbose {
+        eprintln!("$ {shown}");
+    }
+
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(&hook.cmd)
+        .envs(hook.values.iter().cloned())
+        .current_dir(dir)
+        .status()
+        .context(&format!("unable to run post-evoke hook `{shown}`"))?;
+
+    match status.success() {
+        true => Ok(()),
+        false => Err(HookFailed(shown, status.code())),
+    }
+}
+
+/// Finds every recipe to evoke: installed recipes by name, `-` from stdin, and `--file`s.
+///
+/// Recipes that are read from stdin or a file are used as they are, without being installed.
+fn select_recipes(
+    args: &Evoke,
+    user_recipes: &HashMap<String, Recipe>,
+) -> Result<Vec<Recipe>, Error> {
+    // There is an error if no recipes are provided
+    if args.recipes.is_empty() && args.file.is_empty() {
+        return Err(NoneSpecified("recipes".into()));
+    }
+
+    let non_existant_recipes: Vec<String> = args
+        .recipes
+        .iter()
+        .filter(|r| *r != "-" && !user_recipes.contains_key(*r))
+        .cloned()
+        .collect();
+
+    // There is an error if there are any non-existent recipes specified by the user
+    if !non_existant_recipes.is_empty() {
+        return Err(Invalid("recipe(s)".into(), Some(non_existant_recipes)));
+    }
+
+    let mut recipes = vec![];
+    for r in &args.recipes {
+        let recipe = match r.as_str() {
+            "-" => {
+                let contents = io::read_to_string(io::stdin()).context("unable to read stdin")?;
+                read_recipe(&contents, "stdin")?
+            }
+            name => user_recipes
+                .get(name)
+                .cloned()
+                .expect("Invalid recipes should have been filtered out."),
+        };
+        recipes.push(recipe);
+    }
+
+    for path in &args.file {
+        let source = path.display().to_string();
+        let contents =
+            fs::read_to_string(path).context(&format!("unable to read recipe `{source}`"))?;
+        recipes.push(read_recipe(&contents, &source)?);
+    }
+
+    Ok(recipes)
+}
+
+/// Parses a recipe that isn't installed, in any format that `mk` can read.
+fn read_recipe(contents: &str, source: &str) -> Result<Recipe, Error> {
+    deserialise_recipe(contents).ok_or_else(|| Invalid("recipe".into(), Some(vec![source.into()])))
+}
+
+/// A filesystem operation that `mk evoke` intends to perform.
+#[derive(Debug)]
+enum Operation {
+    CreateDir,
+    /// Writes a text file with the given contents and permission bits, if any.
+    WriteFile(String, Option<u32>),
+    /// Writes a binary file with the given contents and permission bits, if any.
+    WriteBinary(Vec<u8>, Option<u32>),
+    /// Creates a symlink pointing to the given target.
+    CreateSymlink(PathBuf),
+    AppendFile(String),
+    /// Writes the result of a three-way merge, which may contain conflict markers.
+    MergeFile {
+        content: String,
+        clean: bool,
+    },
+    /// Moves an existing file out of the way, to `*.orig`.
+    BackupFile,
+}
+
+/// A single operation in an evoke plan, with every substitution already resolved.
+#[derive(Debug)]
+struct Planned {
+    /// The destination after substitution.
+    path: PathBuf,
+    operation: Operation,
+    /// Whether the destination already exists, or is written earlier in the same plan.
+    conflict: bool,
+    /// The shell substitutions that were run to produce this operation, and what they resolved
+    /// to.
+    shell: Vec<(String, Option<String>)>,
+}
+
+/// Resolves a recipe's contents into the operations needed to instantiate it in `dir`.
+fn build_plan(
     dir: &Path,
-    contents: &Vec<RecipeItem>,
-    extra_args: &Evoke,
+    contents: &[RecipeItem],
     re: &ReplaceFmt,
-) -> io::Result<()> {
-    // If the intended destination does not exist, make it.
-    if !dir.is_dir() {
-        fs::create_dir_all(dir)?;
-    }
+    resolver: &Resolver,
+) -> Vec<Planned> {
+    let resolve = |value: &str| resolver.resolve(value);
 
-    for content in contents {
-        let dest = dir.join(content.name());
-        ensure_parent(&dest)?;
+    let mut plan = vec![Planned {
+        path: dir.to_path_buf(),
+        operation: Operation::CreateDir,
+        conflict: false,
+        shell: vec![],
+    }];
 
-        if extra_args.verbose {
-            eprintln!("{}", &dest.display());
+    for content in contents {
+        // Perform substitutions on the name; conditional blocks can leave a path component empty,
+        // in which case the item is skipped
+        let name = re.replace_with(&content.name(), resolve);
+        if name.is_empty() || name.split(std::path::is_separator).any(str::is_empty) {
+            continue;
         }
+        let path = dir.join(name);
 
-        match content {
+        let operation = match content {
             RecipeItem::File(file) => {
-                // perform substitutions on the name and contents
-                let name = re.replace_with(&dest.to_string_lossy(), run_shell);
-                let content = re.replace_with(&file.content, run_shell);
+                // perform substitutions on the contents
+                let content = re.replace_with(&file.content, resolve);
 
-                // Stop if a file would be overwritten unless the user has explicitly suppressed
-                // it.
-                if dest.is_file() && !extra_args.suppress_warnings {
-                    use std::io::ErrorKind::*;
-                    return Err(io::Error::new(
-                        AlreadyExists,
-                        format!("'{}' already exists.", file.name.display()),
-                    ));
-                }
+                Operation::WriteFile(content, file.mode)
+            }
+            // Binary files are copied exactly as they are
+            RecipeItem::Binary(file) => Operation::WriteBinary(file.bytes.clone(), file.mode),
+            RecipeItem::Symlink(link) => {
+                let target = re.replace_with(&link.target.to_string_lossy(), resolve);
 
-                fs::write(&name, content)?;
+                Operation::CreateSymlink(target.into())
             }
-            RecipeItem::Directory(dir_name) => {
-                // Perform substitutions on the dirname
-                let name = re.replace_with(&dir_name.to_string_lossy(), run_shell);
-                let dest = dir.join(name);
+            RecipeItem::Directory(_) => Operation::CreateDir,
+        };
 
-                fs::create_dir_all(&dest)?;
+        plan.push(Planned {
+            path,
+            operation,
+            conflict: false,
+            shell: resolver.take_log(),
+        });
+    }
+
+    plan
+}
+
+/// Flags every file that already exists, or that an earlier operation in the plan writes.
+fn mark_conflicts(plan: &mut [Planned]) {
+    let mut written = HashSet::new();
+
+    for planned in plan.iter_mut() {
+        let exists = match planned.operation {
+            Operation::WriteFile(..) | Operation::WriteBinary(..) => planned.path.is_file(),
+            // Anything in the way of a symlink conflicts, including another (possibly broken) one
+            Operation::CreateSymlink(_) => planned.path.symlink_metadata().is_ok(),
+            _ => continue,
+        };
+
+        let fresh = written.insert(planned.path.clone());
+        planned.conflict = exists || !fresh;
+    }
+}
+
+/// Refuses symlinks that would let the evoke write outside `dir`.
+///
+/// Nothing is ever written through a symlink the same evoke creates, and a symlink may only point
+/// outside `dir` (with an absolute target, or by climbing out with `..`) if the user allows it.
+fn check_links(plan: &[Planned], dir: &Path, allow_external: bool) -> Result<(), Error> {
+    let links: HashMap<&Path, &Path> = plan
+        .iter()
+        .filter_map(|planned| match &planned.operation {
+            Operation::CreateSymlink(target) => Some((planned.path.as_path(), target.as_path())),
+            _ => None,
+        })
+        .collect();
+
+    let through: Vec<_> = plan
+        .iter()
+        .filter(|planned| {
+            let mut ancestors = planned.path.ancestors().skip(1);
+            ancestors.any(|ancestor| ancestor.starts_with(dir) && links.contains_key(ancestor))
+        })
+        .map(|planned| planned.path.display().to_string())
+        .collect();
+    if !through.is_empty() {
+        return Err(Invalid(
+            "recipe, these would be written through a symlink it creates".into(),
+            Some(through),
+        ));
+    }
+
+    let mut external: Vec<_> = links
+        .iter()
+        .filter(|(path, target)| !allow_external && escapes(dir, path, target))
+        .map(|(path, target)| format!("{} -> {}", path.display(), target.display()))
+        .collect();
+    if !external.is_empty() {
+        external.sort();
+        external.push("Use --allow-external-links to create them anyway.".into());
+        return Err(Invalid(
+            "recipe, these symlinks point outside the directory".into(),
+            Some(external),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Whether the symlink at `path` (in `dir`) resolves to somewhere outside `dir`, judging by its
+/// target alone.
+fn escapes(dir: &Path, path: &Path, target: &Path) -> bool {
+    use std::path::Component;
+
+    let parent = path.parent().unwrap_or(dir);
+    let Ok(parent) = parent.strip_prefix(dir) else {
+        return true;
+    };
+
+    let mut depth = parent.components().count();
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth == 0 => return true,
+            Component::ParentDir => depth -= 1,
+            Component::RootDir | Component::Prefix(_) => return true,
+        }
+    }
+
+    false
+}
+
+/// Three-way merges every conflicting file that a previous evoke wrote.
+///
+/// The recipe output recorded in the manifest is the common ancestor of the file as it is now and
+/// the new recipe output. Clashing edits are left in the file with conflict markers.
+fn merge_tracked(plan: &mut [Planned], dir: &Path, manifest: &Manifest) {
+    for planned in plan.iter_mut().filter(|p| p.conflict) {
+        let Operation::WriteFile(new, _) = &planned.operation else {
+            continue;
+        };
+
+        let relative = planned.path.strip_prefix(dir).unwrap_or(&planned.path);
+        let (Some(base), Ok(current)) = (
+            manifest.files.get(relative),
+            fs::read_to_string(&planned.path),
+        ) else {
+            continue;
+        };
+
+        let (content, clean) = match diffy::merge(base, &current, new) {
+            Ok(merged) => (merged, true),
+            Err(conflicted) => (conflicted, false),
+        };
+
+        planned.operation = Operation::MergeFile { content, clean };
+        planned.conflict = false;
+    }
+}
+
+/// Applies a conflict strategy to every conflicting file in the plan.
+///
+/// Fails if the strategy (or the user, when prompted) chooses to abort.
+fn resolve_conflicts(
+    plan: Vec<Planned>,
+    strategy: ConflictStrategy,
+) -> Result<Vec<Planned>, Error> {
+    use ConflictStrategy::*;
+
+    let mut resolved = vec![];
+    // The latest planned contents of each file, so that prompts diff against what would be there
+    let mut planned_contents: HashMap<PathBuf, String> = HashMap::new();
+
+    for mut planned in plan {
+        // Only text files can be diffed and appended to
+        let text = match &planned.operation {
+            Operation::WriteFile(content, _) => Some(content.clone()),
+            Operation::WriteBinary(..) | Operation::CreateSymlink(_) => None,
+            _ => {
+                resolved.push(planned);
+                continue;
+            }
+        };
+
+        if !planned.conflict {
+            if let Some(content) = text {
+                planned_contents.insert(planned.path.clone(), content);
+            }
+            resolved.push(planned);
+            continue;
+        }
+
+        let existing = match planned_contents.get(&planned.path) {
+            Some(existing) => existing.clone(),
+            None => fs::read_to_string(&planned.path).unwrap_or_default(),
+        };
+        let diff = text.as_deref().map(|content| (existing.as_str(), content));
+
+        let choice = match strategy {
+            Prompt => prompt_conflict(&planned.path, diff).context("unable to read response")?,
+            strategy => strategy,
+        };
+
+        match (choice, &text) {
+            (Abort | Prompt, _) => {
+                return Err(DestructionWarning(planned.path.display().to_string()));
+            }
+            (Skip, _) => continue,
+            (Overwrite, _) => {}
+            (Backup, _) => resolved.push(Planned {
+                path: planned.path.clone(),
+                operation: Operation::BackupFile,
+                conflict: false,
+                shell: vec![],
+            }),
+            (Append, Some(content)) => {
+                planned.operation = Operation::AppendFile(content.clone());
+                planned_contents.insert(planned.path.clone(), existing + content);
+                resolved.push(planned);
+                continue;
+            }
+            (Append, None) => {
+                warning!(
+                    "cannot append to '{}' as it is not a text file, skipping.",
+                    planned.path.display()
+                );
+                continue;
             }
         }
+
+        if let Some(content) = text {
+            planned_contents.insert(planned.path.clone(), content);
+        }
+        resolved.push(planned);
     }
 
-    Ok(())
+    Ok(resolved)
 }
 
-/// Ensures that all parent directories of a file exist.
-fn ensure_parent(path: &Path) -> io::Result<()> {
-    let parent = match path.parent() {
-        Some(p) => p,
-        None => return Ok(()),
+/// Shows the user how a conflicting file would change and asks what to do with it.
+///
+/// `diff` holds the existing and new contents of a text file, or is `None` for a binary file or
+/// symlink, which can't be appended to.
+fn prompt_conflict(path: &Path, diff: Option<(&str, &str)>) -> io::Result<ConflictStrategy> {
+    use ConflictStrategy::*;
+
+    let options = match diff {
+        Some((existing, new)) => {
+            let patch = diffy::create_patch(existing, new);
+            eprint!("{}", PatchFormatter::new().with_color().fmt_patch(&patch));
+            "[s]kip, [o]verwrite, [b]ackup, [a]ppend, or [q]uit"
+        }
+        None => "[s]kip, [o]verwrite, [b]ackup, or [q]uit",
     };
 
-    if !parent.is_dir() {
-        fs::create_dir_all(parent)?;
+    loop {
+        eprint!("'{}' already exists. {options}? ", path.display());
+        io::stderr().flush()?;
+
+        let mut response = String::new();
+        // EOF means that nobody is there to answer
+        if io::stdin().read_line(&mut response)? == 0 {
+            return Ok(Abort);
+        }
+
+        match response.trim() {
+            "s" | "skip" => return Ok(Skip),
+            "o" | "overwrite" => return Ok(Overwrite),
+            "b" | "backup" => return Ok(Backup),
+            "a" | "append" if diff.is_some() => return Ok(Append),
+            "q" | "quit" => return Ok(Abort),
+            _ => continue,
+        }
     }
+}
 
-    Ok(())
+/// The path that `--on-conflict backup` moves an existing file to.
+fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".orig");
+    PathBuf::from(backup)
+}
+
+/// Performs a single planned operation, recording it in `tx` so that it can be undone.
+fn apply(planned: &Planned, tx: &mut Transaction, verbose: bool) -> io::Result<()> {
+    if verbose {
+        eprintln!("{}", planned.path.display());
+    }
+
+    match &planned.operation {
+        Operation::CreateDir => tx.create_dir_all(&planned.path),
+        Operation::WriteFile(content, mode) => write_file(tx, &planned.path, content, *mode),
+        Operation::WriteBinary(bytes, mode) => write_file(tx, &planned.path, bytes, *mode),
+        Operation::CreateSymlink(target) => {
+            if let Some(parent) = planned.path.parent() {
+                tx.create_dir_all(parent)?;
+            }
+            tx.symlink(target, &planned.path)
+        }
+        Operation::AppendFile(content) => tx.append(&planned.path, content),
+        Operation::MergeFile { content, clean } => {
+            if !clean {
+                warning!("conflicts merging '{}'", planned.path.display());
+            }
+            tx.write(&planned.path, content)
+        }
+        Operation::BackupFile => tx.rename(&planned.path, &backup_path(&planned.path)),
+    }
+}
+
+/// Writes a file from the recipe, creating its parent directories.
+///
+/// A file only has its mode changed if the recipe gives one, so overwriting e.g. an executable
+/// script doesn't take away its +x. Even then, the mode only restricts the file (see
+/// `Transaction::restrict_mode`), so the user's umask is respected.
+fn write_file(
+    tx: &mut Transaction,
+    path: &Path,
+    content: impl AsRef<[u8]>,
+    mode: Option<u32>,
+) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        tx.create_dir_all(parent)?;
+    }
+
+    tx.write(path, content)?;
+    match mode {
+        Some(mode) => tx.restrict_mode(path, mode),
+        None => Ok(()),
+    }
+}
+
+/// Prints the planned operations for `--dry-run`.
+fn report_plan(plan: &[Planned], strategy: ConflictStrategy) {
+    use ConflictStrategy::*;
+
+    for planned in plan {
+        let path = &planned.path;
+        match (&planned.operation, planned.conflict, strategy) {
+            (Operation::CreateDir, ..) if path.is_dir() => {}
+            (Operation::CreateDir, ..) => report("mkdir", path, None),
+            (Operation::MergeFile { clean: true, .. }, ..) => report("merge", path, None),
+            (Operation::MergeFile { clean: false, .. }, ..) => {
+                report("merge", path, Some("conflicts will be marked"))
+            }
+            (Operation::CreateSymlink(target), false, _) => {
+                report("link", path, Some(&format!("-> {}", target.display())))
+            }
+            (_, false, _) => report("write", path, None),
+            (_, true, Abort) => report(
+                "conflict",
+                path,
+                Some("already exists, use -s to overwrite"),
+            ),
+            (_, true, Skip) => report("skip", path, None),
+            (_, true, Overwrite) => report("overwrite", path, None),
+            (_, true, Backup) => report(
+                "backup",
+                path,
+                Some(&format!("moved to {}", backup_path(path).display())),
+            ),
+            (_, true, Append) => report("append", path, None),
+            (_, true, Prompt) => report("prompt", path, Some("already exists")),
+        }
+
+        for (cmd, out) in &planned.shell {
+            match out {
+                Some(out) => println!("{:<9}   $ {cmd} => {out:?}", ""),
+                None => println!("{:<9}   $ {cmd} => (failed)", ""),
+            }
+        }
+    }
+}
+
+/// Prints a single planned operation for `--dry-run`.
+fn report(action: &str, path: &Path, note: Option<&str>) {
+    let padded = format!("{action:<9}");
+    let action = match action {
+        "conflict" => padded.red(),
+        "overwrite" | "backup" | "append" | "prompt" | "merge" => padded.yellow(),
+        "skip" => padded.normal(),
+        _ => padded.green(),
+    };
+
+    match note {
+        Some(note) => println!("{action} {} ({note})", path.display()),
+        None => println!("{action} {}", path.display()),
+    }
+}
+
+/// The prefix of values that are evaluated every time they are used, instead of once per evoke.
+const FRESH_PREFIX: &str = "mk::fresh:";
+
+/// Evaluates substitution values, remembering each result for the rest of the evoke.
+///
+/// This keeps values consistent between files (e.g. the date cannot change partway through), and
+/// means each command runs once no matter how often it is used. Values marked with
+/// `mk::fresh:` are evaluated every time instead.
+#[derive(Debug, Default)]
+struct Resolver {
+    /// Whether commands should be refused rather than run in a shell.
+    no_shell: bool,
+    /// The commands that were refused because of `no_shell`.
+    blocked: RefCell<Vec<String>>,
+    cache: RefCell<HashMap<String, Option<String>>>,
+    /// The shell commands run since the log was last taken, and what they resolved to.
+    log: RefCell<Vec<(String, Option<String>)>>,
+}
+
+impl Resolver {
+    /// Evaluates a substitution value.
+    fn resolve(&self, value: &str) -> Option<String> {
+        if let Some(value) = value.strip_prefix(FRESH_PREFIX) {
+            return self.run(value);
+        }
+
+        if let Some(cached) = self.cache.borrow().get(value) {
+            return cached.clone();
+        }
+
+        let out = self.run(value);
+        self.cache
+            .borrow_mut()
+            .insert(value.to_string(), out.clone());
+        out
+    }
+
+    /// Evaluates a value, logging it if it had to be run in a shell.
+    fn run(&self, value: &str) -> Option<String> {
+        if !runs_shell(value) {
+            return run_shell(value);
+        }
+
+        if self.no_shell {
+            let mut blocked = self.blocked.borrow_mut();
+            if !blocked.iter().any(|b| b == value) {
+                blocked.push(value.to_string());
+            }
+            return None;
+        }
+
+        let out = run_shell(value);
+        self.log.borrow_mut().push((value.to_string(), out.clone()));
+        out
+    }
+
+    /// Takes the shell commands run since the log was last taken.
+    fn take_log(&self) -> Vec<(String, Option<String>)> {
+        self.log.take()
+    }
+}
+
+/// Returns `true` if evaluating a substitution value would run a shell command.
+///
+/// Literal values and built-in functions (prefixed with 'mk::') never run a shell.
+pub(super) fn runs_shell(value: &str) -> bool {
+    let value = value.strip_prefix(FRESH_PREFIX).unwrap_or(value);
+    !value.starts_with("mk::")
+}
+
+/// The prefix of values that are substituted as they are, with no evaluation.
+const LITERAL_PREFIX: &str = "mk::literal:";
+
+/// Marks a value to be substituted as it is.
+fn literal(value: &str) -> String {
+    format!("{LITERAL_PREFIX}{value}")
 }
 
 /// Runs the provided command.
 ///
-/// Calculated reserved values (prefixed with 'mk::') are immediately dumped instead.
+/// Literal values are immediately dumped instead, and built-in functions (prefixed with 'mk::')
+/// are evaluated without a shell. Any other 'mk::' value is dumped without its prefix, as it was
+/// before there were built-ins.
 fn run_shell(cmd: &str) -> Option<String> {
-    // Handle reserved names.
-    if cmd.starts_with("mk::") {
-        let out = cmd.strip_prefix("mk::").unwrap().to_string();
-        return Some(out);
+    // Handle literal and built-in values.
+    if let Some(value) = cmd.strip_prefix(LITERAL_PREFIX) {
+        return Some(value.to_string());
+    }
+    if let Some(call) = cmd.strip_prefix("mk::") {
+        if !is_builtin(call) {
+            return Some(call.to_string());
+        }
+        let out = builtin(call);
+        if out.is_none() {
+            warning!("could not evaluate built-in '{}'", cmd);
+        }
+        return out;
     }
-
     let output = Command::new("sh").arg("-c").arg(cmd).output().ok();
 
     match output {
@@ -178,3 +883,264 @@ fn run_shell(cmd: &str) -> Option<String> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDir;
+
+    fn planned(path: &str, operation: Operation) -> Planned {
+        Planned {
+            path: Path::new("/out").join(path),
+            operation,
+            conflict: false,
+            shell: vec![],
+        }
+    }
+
+    #[test]
+    fn hook_values_cannot_inject_shell() {
+        let re = ReplaceFmt::new(
+            HashMap::from([(
+                "name".to_string(),
+                literal("x\"; touch pwned; echo \"$(id)"),
+            )]),
+            ("{{", "}}"),
+            InvalidTokenStrategy::Preserve,
+        );
+        let hook = Hook::render(
+            "echo \"{{name}}\" {{name | upper}}",
+            &re,
+            &Resolver::default(),
+        );
+
+        assert_eq!(hook.cmd, "echo \"${MKDEV_VALUE_0}\" ${MKDEV_VALUE_1}");
+        assert_eq!(hook.values[1].1, "X\"; TOUCH PWNED; ECHO \"$(ID)");
+
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&hook.cmd)
+            .envs(hook.values.iter().cloned())
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "x\"; touch pwned; echo \"$(id) X\"; TOUCH PWNED; ECHO \"$(ID)\n"
+        );
+        assert_eq!(
+            hook.display(),
+            "echo \"x\"; touch pwned; echo \"$(id)\" X\"; TOUCH PWNED; ECHO \"$(ID)"
+        );
+    }
+
+    #[test]
+    fn symlinks_cannot_lead_writes_out_of_the_directory() {
+        let dir = Path::new("/out");
+        let link = |path, target: &str| planned(path, Operation::CreateSymlink(target.into()));
+        let file = |path| planned(path, Operation::WriteFile(String::new(), None));
+
+        let inside = [
+            link("a/b/up", "../../c"),
+            link("here", "./x/y"),
+            file("a/b/c"),
+        ];
+        assert!(check_links(&inside, dir, false).is_ok());
+
+        for target in ["/etc", "../..", "x/../../../y"] {
+            let plan = [link("a/link", target)];
+            assert!(check_links(&plan, dir, false).is_err(), "{target}");
+            assert!(check_links(&plan, dir, true).is_ok(), "{target}");
+        }
+
+        let through = [link("sub", "inside"), file("sub/file")];
+        assert!(check_links(&through, dir, true).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn overwriting_keeps_the_mode_unless_the_recipe_sets_one() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = TestDir::new("evoke-mode");
+        let script = root.join("run.sh");
+        fs::write(&script, "").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        let mut tx = Transaction::new();
+        write_file(&mut tx, &script, "echo", None).unwrap();
+        write_file(&mut tx, &root.join("new.sh"), "echo", None).unwrap();
+        fs::write(root.join("plain"), "").unwrap();
+        assert_eq!(mode(&script), 0o755);
+        assert_eq!(mode(&root.join("new.sh")), mode(&root.join("plain")));
+
+        // The usual mode can still be set on purpose
+        write_file(&mut tx, &script, "echo", Some(0o644)).unwrap();
+        assert_eq!(mode(&script), 0o644);
+        write_file(&mut tx, &script, "echo", Some(0o700)).unwrap();
+        assert_eq!(mode(&script), 0o700);
+    }
+
+    #[test]
+    fn tracked_files_are_merged_with_their_last_output() {
+        let dir = TestDir::new("evoke-merge");
+        let mut manifest = Manifest::default();
+        let base = "one\ntwo\nthree\nfour\nfive\n";
+        for name in ["clean", "conflicted", "untracked"] {
+            if name != "untracked" {
+                manifest.files.insert(name.into(), base.into());
+            }
+        }
+        fs::write(dir.join("clean"), "ONE\ntwo\nthree\nfour\nfive\n").unwrap();
+        fs::write(dir.join("conflicted"), "one\ntwo\nmine\nfour\nfive\n").unwrap();
+        fs::write(dir.join("untracked"), "mine\n").unwrap();
+
+        let write = |name: &str, content: &str| Planned {
+            path: dir.join(name),
+            operation: Operation::WriteFile(content.into(), None),
+            conflict: true,
+            shell: vec![],
+        };
+        let mut plan = [
+            write("clean", "one\ntwo\nthree\nfour\nFIVE\n"),
+            write("conflicted", "one\ntwo\ntheirs\nfour\nfive\n"),
+            write("untracked", "theirs\n"),
+        ];
+        merge_tracked(&mut plan, &dir, &manifest);
+
+        let Operation::MergeFile { content, clean } = &plan[0].operation else {
+            panic!("{:?}", plan[0].operation);
+        };
+        assert!(clean);
+        assert_eq!(content, "ONE\ntwo\nthree\nfour\nFIVE\n");
+        assert!(!plan[0].conflict);
+
+        let Operation::MergeFile { content, clean } = &plan[1].operation else {
+            panic!("{:?}", plan[1].operation);
+        };
+        assert!(!clean);
+        assert!(content.contains("<<<<<<<"));
+        assert!(content.contains("mine") && content.contains("theirs"));
+        assert!(content.contains(">>>>>>>"));
+
+        // Without a base there is nothing to merge, so it is left to the conflict strategy
+        assert!(matches!(plan[2].operation, Operation::WriteFile(..)));
+        assert!(plan[2].conflict);
+    }
+
+    #[test]
+    fn conflicts_are_resolved_by_the_strategy() {
+        use ConflictStrategy::*;
+
+        let conflicting = |path, operation| Planned {
+            conflict: true,
+            ..planned(path, operation)
+        };
+        let write = |content: &str| Operation::WriteFile(content.into(), None);
+        let plan = || {
+            vec![
+                planned("dir", Operation::CreateDir),
+                planned("new", write("new")),
+                conflicting("text", write("text")),
+                conflicting("logo.png", Operation::WriteBinary(vec![0], None)),
+                conflicting("link", Operation::CreateSymlink("new".into())),
+            ]
+        };
+        // What is left of each entry in the plan
+        let summary = |plan: Vec<Planned>| -> Vec<String> {
+            plan.iter()
+                .map(|planned| {
+                    let path = planned.path.strip_prefix("/out").unwrap().display();
+                    match &planned.operation {
+                        Operation::CreateDir => format!("mkdir {path}"),
+                        Operation::WriteFile(content, _) => format!("write {path}: {content}"),
+                        Operation::WriteBinary(..) => format!("write {path}"),
+                        Operation::CreateSymlink(_) => format!("link {path}"),
+                        Operation::AppendFile(content) => format!("append {path}: {content}"),
+                        Operation::BackupFile => format!("backup {path}"),
+                        Operation::MergeFile { .. } => format!("merge {path}"),
+                    }
+                })
+                .collect()
+        };
+
+        assert!(matches!(
+            resolve_conflicts(plan(), Abort),
+            Err(DestructionWarning(path)) if path == "/out/text"
+        ));
+        assert_eq!(
+            summary(resolve_conflicts(plan(), Skip).unwrap()),
+            ["mkdir dir", "write new: new"]
+        );
+        assert_eq!(
+            summary(resolve_conflicts(plan(), Overwrite).unwrap()),
+            [
+                "mkdir dir",
+                "write new: new",
+                "write text: text",
+                "write logo.png",
+                "link link"
+            ]
+        );
+        assert_eq!(
+            summary(resolve_conflicts(plan(), Backup).unwrap()),
+            [
+                "mkdir dir",
+                "write new: new",
+                "backup text",
+                "write text: text",
+                "backup logo.png",
+                "write logo.png",
+                "backup link",
+                "link link"
+            ]
+        );
+        // Binary files and symlinks can't be appended to, so they are skipped
+        assert_eq!(
+            summary(resolve_conflicts(plan(), Append).unwrap()),
+            ["mkdir dir", "write new: new", "append text: text"]
+        );
+    }
+
+    #[test]
+    fn files_written_earlier_in_the_plan_are_conflicts_too() {
+        let write = |content: &str| Operation::WriteFile(content.into(), None);
+        let plan = || {
+            let mut plan = vec![
+                planned("README", write("one\n")),
+                planned("README", write("two\n")),
+            ];
+            mark_conflicts(&mut plan);
+            plan
+        };
+
+        let skipped = resolve_conflicts(plan(), ConflictStrategy::Skip).unwrap();
+        assert_eq!(skipped.len(), 1);
+        assert!(matches!(&skipped[0].operation, Operation::WriteFile(c, _) if c == "one\n"));
+
+        let appended = resolve_conflicts(plan(), ConflictStrategy::Append).unwrap();
+        assert!(matches!(&appended[0].operation, Operation::WriteFile(c, _) if c == "one\n"));
+        assert!(matches!(&appended[1].operation, Operation::AppendFile(c) if c == "two\n"));
+    }
+
+    #[test]
+    fn values_are_evaluated_once_unless_fresh() {
+        let dir = TestDir::new("evoke-resolver");
+        // Each command counts how many times it has been run
+        let counter = |name: &str| {
+            let path = dir.join(name);
+            format!("echo >> '{0}'; wc -l < '{0}' | tr -d ' '", path.display())
+        };
+        let cached = counter("cached");
+        let fresh = format!("{FRESH_PREFIX}{}", counter("fresh"));
+
+        let resolver = Resolver::default();
+        let results: Vec<_> = [&cached, &fresh, &cached, &fresh, &cached]
+            .iter()
+            .map(|value| resolver.resolve(value).unwrap())
+            .collect();
+        assert_eq!(results, ["1", "1", "1", "2", "1"]);
+        assert_eq!(fs::read_to_string(dir.join("cached")).unwrap(), "\n");
+        assert_eq!(resolver.take_log().len(), 3);
+    }
+}
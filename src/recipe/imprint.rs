@@ -4,27 +4,65 @@
 //! the current directory recursively and stores the relative path and contents of all text files
 //! and subdirectories. Upon completion of this recursive walk, the contents are packed into a
 //! recipe struct and stored to the recipe directory.
-use super::{Language, Recipe, recipe_dir};
+use super::checkout::Checkout;
+use super::templatize::Templatizer;
+use super::update::ContentChange;
+use super::variables::parse_key_values;
+use super::version::deserialise_recipe;
+use super::{Language, Recipe, Requirements, recipe_dir, writable_source};
 use crate::cli::Imprint;
-use crate::content::{build_walk, make_contents};
+use crate::content::{build_walk, imprint_paths, imprint_root, make_contents, without_empty_dirs};
 use crate::mkdev_error::{
     Error::{self, *},
     ResultExt,
 };
+use crate::symlink_strategy::SymlinkStrategy;
+use crate::warning;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use colored::Colorize;
 use hyperpolyglot::get_language_breakdown;
 use ignore::Walk;
 
 /// Imprints a recipe using arguments from the command line, and post processes it accordingly.
 pub fn imprint_recipe(args: Imprint, user_recipes: HashMap<String, Recipe>) -> Result<(), Error> {
-    let walker = build_walk(&args)?;
-    let new = Recipe::imprint(args.recipe, args.description, walker)
-        .context("unable to read current_working directory for the recipe")?;
+    let root = imprint_root(&args)?;
+    // A git revision is extracted, and imprinted in place of the root
+    let checkout = match &args.git_rev {
+        Some(rev) => Some(Checkout::new(&root, rev)?),
+        None => None,
+    };
+    let root = checkout
+        .as_ref()
+        .map_or(root, |checkout| checkout.dir.clone());
+    let paths = imprint_paths(&args, &root)?;
+    let walker = build_walk(&args, &root, &paths)?;
+    let mut new = Recipe::imprint(args.recipe, args.description, walker, &root, args.symlinks)
+        .context(&format!("unable to read {} for the recipe", root.display()))?;
+
+    if !args.include.is_empty() {
+        new.contents = without_empty_dirs(new.contents);
+    }
+
+    if !args.templatize.is_empty() {
+        let values = parse_key_values(&args.templatize, "--templatize")?;
+        new.templatize(&Templatizer::new(&values)?);
+    }
+
+    let dir = recipe_dir().context("unable to locate recipe directory")?;
+    if args.update {
+        let (updated, changes) = update_recipe(new, &dir, &user_recipes)?;
+        if changes.is_empty() {
+            println!("`{}` is already up to date.", updated.name);
+            return Ok(());
+        }
+        report_changes(&changes);
+        new = updated;
+    }
 
     if let Some(path) = args.to_nix {
         let nix_expression = ser_nix::to_string(&new).context("recipe")?;
@@ -34,34 +72,130 @@ pub fn imprint_recipe(args: Imprint, user_recipes: HashMap<String, Recipe>) -> R
         return Ok(());
     }
 
-    let destructive = user_recipes.iter().any(|(recipe, _)| recipe == &new.name);
-
-    if destructive && !args.suppress_warnings {
-        return Err(DestructionWarning(new.name));
+    if !args.suppress_warnings {
+        ensure_not_overwriting(&new, &dir, &user_recipes)?;
     }
 
     let save_location = new.save().context("Unable to save instantiated recipe")?;
 
     println!("{}", &save_location.display());
 
+    // A recipe of the same name earlier in the recipe path still takes precedence
+    if let Some(winner) = Recipe::gather()
+        .context("unable to read recipes")?
+        .remove(&new.name)
+        && !winner
+            .is_writable()
+            .context("unable to locate recipe directory")?
+    {
+        let dir = winner.source_dir().expect("Gathered recipes have a source");
+        warning!(
+            "`{}` is shadowed by the recipe of the same name in {}.",
+            new.name,
+            dir.display()
+        );
+    }
+
     Ok(())
 }
 
+/// Fails if saving a newly imprinted recipe would replace the file of a recipe in `dir`, the
+/// recipe directory.
+///
+/// Whether that recipe takes precedence over others of the same name doesn't matter, since its
+/// file is overwritten either way. An updated recipe is meant to replace its file.
+fn ensure_not_overwriting(
+    new: &Recipe,
+    dir: &Path,
+    user_recipes: &HashMap<String, Recipe>,
+) -> Result<(), Error> {
+    match new.source.is_none() && writable_source(dir, &new.name, user_recipes).exists() {
+        true => Err(DestructionWarning(new.name.clone())),
+        false => Ok(()),
+    }
+}
+
+/// Applies a new imprint to the recipe of the same name in `dir`, the recipe directory.
+///
+/// The recipe is read again from its file, since installed recipes are resolved (see `compose`),
+/// and the new imprint would otherwise replace what the recipe is built on.
+fn update_recipe(
+    new: Recipe,
+    dir: &Path,
+    user_recipes: &HashMap<String, Recipe>,
+) -> Result<(Recipe, Vec<ContentChange>), Error> {
+    let source = writable_source(dir, &new.name, user_recipes);
+    if !source.is_file() {
+        return match user_recipes.get(&new.name) {
+            Some(shadowing) => {
+                let dir = shadowing
+                    .source_dir()
+                    .expect("Gathered recipes have a source");
+                Err(NotWritable(new.name, dir.display().to_string()))
+            }
+            None => Err(Invalid("recipe to update".into(), Some(vec![new.name]))),
+        };
+    }
+
+    let context = format!("unable to read {}", source.display());
+    let contents = fs::read_to_string(&source).context(&context)?;
+    let mut recipe = deserialise_recipe(&contents)
+        .ok_or_else(|| Invalid("recipe".into(), Some(vec![source.display().to_string()])))?;
+    recipe.source = Some(source);
+
+    // The gathered recipe of the same name may be another one that shadows this
+    let resolved = recipe.resolve(user_recipes)?;
+    let base = recipe
+        .extends
+        .as_ref()
+        .and_then(|base| user_recipes.get(base));
+    let changes = recipe.update(new, &resolved, base);
+
+    Ok((recipe, changes))
+}
+
+/// Prints the changes that an update makes to a recipe's contents.
+fn report_changes(changes: &[ContentChange]) {
+    for change in changes {
+        let action = match change {
+            ContentChange::Added(_) => format!("{:<9}", "add").green(),
+            ContentChange::Changed(_) => format!("{:<9}", "change").yellow(),
+            ContentChange::Removed(_) => format!("{:<9}", "remove").red(),
+        };
+        println!("{action} {}", change.path().display());
+    }
+}
+
 impl Recipe {
-    /// Create a `Recipe` by imprinting/cloning the contents of the cwd
-    pub fn imprint(name: String, description: Option<String>, walker: Walk) -> io::Result<Self> {
-        let contents = make_contents(walker)?;
+    /// Create a `Recipe` by imprinting/cloning the contents of `walker`, relative to `root`
+    pub fn imprint(
+        name: String,
+        description: Option<String>,
+        walker: Walk,
+        root: &Path,
+        symlinks: SymlinkStrategy,
+    ) -> io::Result<Self> {
+        let contents = make_contents(walker, root, symlinks)?;
 
         let description = description.unwrap_or("".into());
 
-        // Converts HashMap<&name, detected_info> -> Vec<(name, num_matching_files)>
-        let mut breakdown: Vec<_> = get_language_breakdown(".")
-            .iter()
-            .map(|(lang, files)| (*lang, files.len()))
+        // Converts HashMap<&name, detected_info> -> Vec<(name, num_matching_files)>, counting
+        // only the files that were imprinted
+        let imprinted: HashSet<_> = contents.iter().map(|item| root.join(item.path())).collect();
+        let mut breakdown: Vec<_> = get_language_breakdown(root)
+            .into_iter()
+            .map(|(lang, files)| {
+                let count = files
+                    .iter()
+                    .filter(|(_, file)| imprinted.contains(file))
+                    .count();
+                (lang, count)
+            })
+            .filter(|(_, count)| *count > 0)
             .collect();
 
         // Sort languages by number of matching files
-        breakdown.sort_by(|a, b| b.1.cmp(&a.1));
+        breakdown.sort_by_key(|b| std::cmp::Reverse(b.1));
 
         let languages: Vec<_> = breakdown
             .iter()
@@ -78,17 +212,58 @@ impl Recipe {
             contents,
             languages,
             description,
+            extends: None,
+            remove: vec![],
+            patches: vec![],
+            includes: vec![],
+            post_evoke: vec![],
+            requires: Requirements::default(),
+            subs: BTreeMap::new(),
+            variables: vec![],
+            source: None,
+            unresolved: None,
         })
     }
 
-    /// Save the recipe object by serialising self into the data directory
+    /// Save the recipe object by serialising self into the data directory, or back to the file it
+    /// was read from
     pub fn save(&self) -> io::Result<PathBuf> {
-        let mut data_dir = recipe_dir()?;
+        let path = match &self.source {
+            Some(source) => source.clone(),
+            None => recipe_dir()?.join(format!("{}.toml", self.name)),
+        };
+
+        fs::write(&path, toml::to_string_pretty(&self).unwrap())?;
+
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{TestDir, recipe};
+
+    #[test]
+    fn shadowed_recipes_are_not_overwritten_silently() {
+        let root = TestDir::new("imprint");
+        let (writable, project) = (root.join("recipes"), root.join("project"));
+        fs::create_dir_all(&writable).unwrap();
+        fs::create_dir_all(&project).unwrap();
+        fs::write(writable.join("foo.toml"), "").unwrap();
 
-        data_dir.push(format!("{}.toml", self.name));
+        // The project's `foo` takes precedence over the user's
+        let mut shadowing: Recipe = recipe("foo", &[]);
+        shadowing.source = Some(project.join("foo.toml"));
+        let recipes = HashMap::from([("foo".to_string(), shadowing)]);
 
-        fs::write(&data_dir, toml::to_string_pretty(&self).unwrap())?;
+        let new: Recipe = recipe("foo", &[]);
+        assert!(matches!(
+            ensure_not_overwriting(&new, &writable, &recipes),
+            Err(DestructionWarning(_))
+        ));
 
-        Ok(data_dir)
+        fs::remove_file(writable.join("foo.toml")).unwrap();
+        assert!(ensure_not_overwriting(&new, &writable, &recipes).is_ok());
     }
 }
@@ -0,0 +1,36 @@
+//! Recipes that include other recipes.
+//!
+//! A recipe can `include` other recipes, optionally mounted under a subdirectory, so that shared
+//! files (e.g. a LICENSE or CI config) live in one recipe instead of being copied into many. See
+//! `compose` for how includes are combined with the recipe's own contents.
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// Another recipe that a recipe is built on.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Include {
+    /// Includes a recipe's contents as they are, e.g. `"license-mit"`.
+    Recipe(String),
+    /// Includes a recipe's contents under a subdirectory, e.g.
+    /// `{ recipe = "ci-github", into = ".github" }`.
+    Mounted { recipe: String, into: PathBuf },
+}
+
+impl Include {
+    /// The name of the included recipe.
+    pub fn recipe(&self) -> &str {
+        match self {
+            Include::Recipe(recipe) | Include::Mounted { recipe, .. } => recipe,
+        }
+    }
+
+    /// The subdirectory that the recipe's contents are placed in, if any.
+    pub fn into(&self) -> Option<&Path> {
+        match self {
+            Include::Recipe(_) => None,
+            Include::Mounted { into, .. } => Some(into),
+        }
+    }
+}
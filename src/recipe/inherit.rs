@@ -0,0 +1,69 @@
+//! Recipes that extend other recipes.
+//!
+//! A recipe that `extends` a base recipe starts from the base's contents, leaves out the paths
+//! listed in `remove`, and can change the base's files with `patches` rather than copying them.
+//! See `compose` for how this is combined with the recipe's own contents.
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// A change to the content of a file inherited from a base recipe.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Patch {
+    /// The file to change.
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub operation: PatchOperation,
+}
+
+/// How a `Patch` changes a file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum PatchOperation {
+    /// Applies a unified diff, e.g. `diff = "@@ -1 +1 @@\n-old\n+new\n"`.
+    Diff { diff: String },
+    /// Adds text to the end of the file.
+    Append { append: String },
+    /// Adds text to the start of the file.
+    Prepend { prepend: String },
+}
+
+impl Patch {
+    /// Applies the patch to the content of a file, or explains why it doesn't apply.
+    pub fn apply(&self, content: &str) -> Result<String, String> {
+        match &self.operation {
+            PatchOperation::Diff { diff } => {
+                let diff = diffy::Patch::from_str(diff).map_err(|e| e.to_string())?;
+                diffy::apply(content, &diff).map_err(|e| e.to_string())
+            }
+            PatchOperation::Append { append } => Ok(format!("{content}{append}")),
+            PatchOperation::Prepend { prepend } => Ok(format!("{prepend}{content}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch(toml: &str) -> Patch {
+        toml::from_str(&format!("path = \"a\"\n{toml}")).unwrap()
+    }
+
+    #[test]
+    fn patches_deserialise_by_operation() {
+        let prepend = patch(r#"prepend = "// header\n""#);
+        assert!(matches!(prepend.operation, PatchOperation::Prepend { .. }));
+        assert_eq!(prepend.apply("body\n").unwrap(), "// header\nbody\n");
+
+        let append = patch(r#"append = "*.log\n""#);
+        assert_eq!(append.apply("target\n").unwrap(), "target\n*.log\n");
+    }
+
+    #[test]
+    fn diffs_must_apply_cleanly() {
+        let diff = patch(r#"diff = "--- a\n+++ b\n@@ -1 +1 @@\n-base\n+child\n""#);
+        assert_eq!(diff.apply("base\n").unwrap(), "child\n");
+        assert!(diff.apply("other\n").is_err());
+    }
+}
@@ -1,7 +1,7 @@
 //! Implementation of `mk list`.
 //!
 //! Supports printing single recipes or all known recipes in various formats.
-use super::Recipe;
+use super::{Recipe, recipe_path};
 use crate::cli::List;
 use crate::config::Config;
 use crate::display::{display_recipes_with_config, repr_tree};
@@ -52,6 +52,11 @@ fn display_all(recipes: Vec<&Recipe>, output_type: OutputType, show_description:
         config.show_descriptions = Some(show_description)
     }
 
+    // Sources only need pointing out when there is more than one place they could be
+    if config.show_sources.is_none() {
+        config.show_sources = Some(recipe_path().is_ok_and(|dirs| dirs.len() > 1));
+    }
+
     match output_type {
         Default => print!("{}", display_recipes_with_config(&recipes, &config)),
         Debug => recipes.iter().for_each(|r| println!("{:#?}", r)),
@@ -0,0 +1,101 @@
+//! The manifest that `mk evoke` leaves behind in the directories it builds to.
+//!
+//! The manifest records which recipes a directory was generated from, along with what each
+//! generated file looked like when it was written. This is the common ancestor `mk evoke --update`
+//! needs to three-way merge a newer version of a recipe into a project that has since been edited.
+use super::{Recipe, fingerprint};
+use crate::mkdev_error::{
+    Error::{self, *},
+    ResultExt,
+};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// The name of the manifest file, relative to the evoked directory.
+pub const MANIFEST_NAME: &str = ".mkdev-manifest.toml";
+
+/// A record of the recipes evoked into a directory.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Manifest {
+    /// The recipes that have been evoked into the directory.
+    #[serde(default)]
+    pub recipes: Vec<EvokedRecipe>,
+    /// The recipe output for each generated file, keyed by its path relative to the directory.
+    #[serde(default)]
+    pub files: BTreeMap<PathBuf, String>,
+}
+
+/// A recipe, and the version of it that was evoked.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EvokedRecipe {
+    pub name: String,
+    pub version: String,
+}
+
+impl Manifest {
+    /// Returns the path of the manifest for a directory.
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join(MANIFEST_NAME)
+    }
+
+    /// Loads the manifest for a directory, or an empty one if the directory has none.
+    pub fn load(dir: &Path) -> Result<Self, Error> {
+        let path = Self::path(dir);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        if !path.is_file() {
+            return Err(Invalid(
+                "evoke manifest, expected a file".into(),
+                Some(vec![path.display().to_string()]),
+            ));
+        }
+
+        let contents = fs::read_to_string(&path).context("unable to read evoke manifest")?;
+        toml::from_str(&contents).context("evoke manifest")
+    }
+
+    /// Records that a recipe was evoked, replacing any earlier record of the same recipe.
+    pub fn record(&mut self, recipe: &Recipe) {
+        self.recipes.retain(|r| r.name != recipe.name);
+        self.recipes.push(EvokedRecipe {
+            name: recipe.name.clone(),
+            version: recipe.version(),
+        });
+    }
+
+    /// Serialises the manifest.
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("Manifests only contain strings and should serialise")
+    }
+}
+
+impl Recipe {
+    /// A short fingerprint of the recipe's contents, used to tell versions of a recipe apart.
+    pub fn version(&self) -> String {
+        let contents = serde_json::to_string(&self.contents)
+            .expect("Recipes are instantiated with serde, and should unwrap");
+
+        fingerprint(&contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDir;
+
+    #[test]
+    fn manifest_does_not_clash_with_project_recipes() {
+        let dir = TestDir::new("manifest");
+        fs::create_dir_all(dir.join(".mkdev/recipes")).unwrap();
+
+        // A `.mkdev` directory is project recipes, not a manifest
+        assert!(Manifest::load(&dir).unwrap().recipes.is_empty());
+        assert_ne!(Manifest::path(&dir), dir.join(".mkdev"));
+    }
+}
@@ -1,27 +1,45 @@
 //! mkdev's core library. Defines the recipe schema and provides tools for working with them.
+mod builtins;
+mod checkout;
+mod compose;
 mod delete;
 mod evoke;
 mod imprint;
+mod include;
+mod inherit;
 mod lang;
 mod list;
+mod manifest;
+mod requires;
+mod templatize;
+mod transaction;
+mod trust;
+mod update;
+mod variables;
 mod version;
 
 pub use delete::*;
 pub use evoke::*;
 pub use imprint::*;
+pub use include::Include;
+pub use inherit::Patch;
 pub use lang::Language;
 pub use list::*;
+pub use manifest::MANIFEST_NAME;
+pub use requires::Requirements;
+pub use variables::Variable;
 
 use version::*;
 
 use crate::config::Config;
 use crate::content::RecipeItem;
+use crate::mkdev_error::Error;
 use crate::warning;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use dirs::data_dir;
 use serde::{Deserialize, Serialize};
@@ -39,43 +57,143 @@ pub struct Recipe {
     pub description: String,
     /// The programming languages (or file formats) found in the recipe's contents.
     pub languages: Vec<Language>,
+    /// A recipe that this recipe is based on (Optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    /// Paths from the base recipe to leave out (Optional)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remove: Vec<PathBuf>,
+    /// Changes to files from the base recipe (Optional)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patches: Vec<Patch>,
+    /// Other recipes that this recipe is built on (Optional)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub includes: Vec<Include>,
+    /// Commands run in the target directory after the recipe is evoked (Optional)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_evoke: Vec<String>,
+    /// What the recipe needs from the machine and the target directory (Optional)
+    #[serde(default, skip_serializing_if = "Requirements::is_empty")]
+    pub requires: Requirements,
+    /// Substitutions that the recipe provides, which take precedence over the user's (Optional)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub subs: BTreeMap<String, String>,
+    /// Values the recipe asks for when it is evoked (Optional)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub variables: Vec<Variable>,
     /// The contents the recipe holds.
     pub contents: Vec<RecipeItem>,
+    /// The file the recipe was read from, if it is installed.
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+    /// Why the recipe couldn't be resolved, if it couldn't. It is then kept as it is written, so
+    /// that it can still be listed, imprinted over, and deleted, but evoking it fails.
+    #[serde(skip)]
+    pub unresolved: Option<Error>,
 }
 
 impl Recipe {
-    /// Gathers all recipes from the user directory.
+    /// Gathers all recipes from every directory in the recipe path.
     ///
-    /// Only files with the .toml extension are checked. An invalid recipe gives a warning.
+    /// Only files with the .toml extension are checked. An invalid recipe gives a warning. When
+    /// more than one directory has a recipe with the same name, the earliest one wins. Recipes
+    /// are returned with their inheritance resolved.
     pub fn gather() -> io::Result<HashMap<String, Recipe>> {
-        let data_dir = recipe_dir()?;
-        let files = fs::read_dir(data_dir)?;
-        let mut recipes: Vec<Recipe> = Vec::new();
+        let mut recipes: HashMap<String, Recipe> = HashMap::new();
 
-        for file in files {
-            let path = file?.path();
-
-            if path.extension() == Some(std::ffi::OsStr::new("toml")) && path.is_file() {
-                let file_contents = fs::read_to_string(&path)?;
-                let recipe = deserialise_recipe(&file_contents);
+        for dir in recipe_path()? {
+            // Directories other than the user's own are optional (e.g. project-local ones)
+            if !dir.is_dir() {
+                continue;
+            }
 
-                match recipe {
-                    Some(recipe) => {
-                        recipes.push(recipe);
-                    }
-                    None => {
-                        warning!("{} is not a valid recipe.", path.display());
+            for file in fs::read_dir(&dir)? {
+                let path = file?.path();
+
+                if path.extension() == Some(std::ffi::OsStr::new("toml")) && path.is_file() {
+                    let file_contents = fs::read_to_string(&path)?;
+                    let recipe = deserialise_recipe(&file_contents);
+
+                    match recipe {
+                        Some(mut recipe) => {
+                            recipe.source = Some(path);
+                            recipes.entry(recipe.name.clone()).or_insert(recipe);
+                        }
+                        None => {
+                            warning!("{} is not a valid recipe.", path.display());
+                        }
                     }
                 }
             }
         }
 
-        let recipes = recipes
-            .iter()
-            .map(|r| (r.name.clone(), r.to_owned()))
-            .collect();
+        Ok(compose::resolve_all(recipes))
+    }
+
+    /// The directory the recipe was read from, if it is installed.
+    pub fn source_dir(&self) -> Option<&Path> {
+        self.source.as_deref().and_then(Path::parent)
+    }
+
+    /// Returns `true` if the recipe was read from the directory that recipes are saved to.
+    pub fn is_writable(&self) -> io::Result<bool> {
+        let writable = recipe_dir()?;
+
+        Ok(self
+            .source_dir()
+            .is_some_and(|dir| same_dir(dir, &writable)))
+    }
+}
+
+/// A short, stable fingerprint of some data.
+///
+/// This is a 64-bit FNV-1a hash, which (unlike `std`'s hasher) is stable between builds, so it
+/// can be saved to disk. It is not cryptographically secure.
+fn fingerprint(data: &str) -> String {
+    let hash = data.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+
+    format!("{hash:016x}")
+}
+
+/// Gets every directory that recipes are read from, in order of precedence.
+///
+/// This is `recipe_path` from the user's config, followed by `recipe_dir` if it isn't already
+/// listed.
+pub fn recipe_path() -> io::Result<Vec<PathBuf>> {
+    let cfg = Config::get().map_err(io::Error::other)?;
+    let writable = recipe_dir()?;
+
+    let mut dirs = cfg.recipe_path.clone();
+    if !dirs.iter().any(|dir| same_dir(dir, &writable)) {
+        dirs.push(writable);
+    }
+
+    Ok(dirs)
+}
+
+/// The file in `dir` (the recipe directory) that holds the recipe called `name`.
+///
+/// This is the file that mkdev changes, even when a recipe of the same name elsewhere on the
+/// recipe path takes precedence over it. It may not exist.
+fn writable_source(dir: &Path, name: &str, recipes: &HashMap<String, Recipe>) -> PathBuf {
+    match recipes
+        .get(name)
+        .and_then(|recipe| recipe.source.as_deref())
+    {
+        Some(source) if source.parent().is_some_and(|parent| same_dir(parent, dir)) => {
+            source.to_path_buf()
+        }
+        _ => dir.join(format!("{name}.toml")),
+    }
+}
 
-        Ok(recipes)
+/// Returns `true` if two paths are the same directory, even if they are written differently.
+fn same_dir(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
     }
 }
 
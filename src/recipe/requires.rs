@@ -0,0 +1,207 @@
+//! What a recipe needs from the machine and the target directory.
+//!
+//! A recipe can declare the programs it expects on PATH (e.g. `cargo`), the oldest mkdev that
+//! understands it, and conditions on the target directory. These are all checked before anything
+//! is written, so a recipe fails up front instead of leaving a half-working project behind.
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+/// The requirements a recipe declares, e.g.
+/// `requires = { binaries = ["cargo"], mkdev = "3.4", dir = ["empty"] }`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Requirements {
+    /// Programs that must be on PATH (Optional)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub binaries: Vec<String>,
+    /// The minimum version of mkdev (Optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mkdev: Option<String>,
+    /// Conditions on the target directory (Optional)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dir: Vec<DirPredicate>,
+}
+
+/// A condition on the directory that a recipe is evoked into.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DirPredicate {
+    /// The directory doesn't exist yet, or has nothing in it.
+    Empty,
+    /// The directory is inside a git work tree.
+    Git,
+    /// The directory is inside a git work tree with no uncommitted changes.
+    GitClean,
+}
+
+impl Requirements {
+    /// Whether there are no requirements.
+    pub fn is_empty(&self) -> bool {
+        self.binaries.is_empty() && self.mkdev.is_none() && self.dir.is_empty()
+    }
+
+    /// Adds the requirements of a recipe that this one is built on.
+    pub fn merge(&mut self, other: &Requirements) {
+        for binary in &other.binaries {
+            if !self.binaries.contains(binary) {
+                self.binaries.push(binary.clone());
+            }
+        }
+        for predicate in &other.dir {
+            if !self.dir.contains(predicate) {
+                self.dir.push(*predicate);
+            }
+        }
+
+        // The newest minimum version is the one that matters
+        let newer = match (&self.mkdev, &other.mkdev) {
+            (Some(own), Some(theirs)) => parse_version(theirs) > parse_version(own),
+            (None, Some(_)) => true,
+            _ => false,
+        };
+        if newer {
+            self.mkdev = other.mkdev.clone();
+        }
+    }
+
+    /// Describes every requirement that isn't met when evoking into `dir`.
+    pub fn unmet(&self, dir: &Path) -> Vec<String> {
+        let mut unmet = vec![];
+
+        for binary in &self.binaries {
+            if !on_path(binary) {
+                unmet.push(format!("`{binary}` on PATH"));
+            }
+        }
+
+        if let Some(minimum) = &self.mkdev {
+            let current = env!("CARGO_PKG_VERSION");
+            match parse_version(minimum) {
+                Some(version) if parse_version(current).is_some_and(|c| c >= version) => {}
+                Some(_) => unmet.push(format!("mkdev {minimum} or newer (this is {current})")),
+                None => unmet.push(format!("mkdev {minimum}, which is not a valid version")),
+            }
+        }
+
+        for predicate in &self.dir {
+            if !predicate.holds(dir) {
+                let what = match predicate {
+                    DirPredicate::Empty => "to be empty",
+                    DirPredicate::Git => "to be in a git repository",
+                    DirPredicate::GitClean => {
+                        "to be in a git repository with no uncommitted changes"
+                    }
+                };
+                unmet.push(format!("`{}` {what}", dir.display()));
+            }
+        }
+
+        unmet
+    }
+}
+
+impl DirPredicate {
+    /// Checks the condition against a target directory, which may not exist yet.
+    fn holds(&self, dir: &Path) -> bool {
+        match self {
+            DirPredicate::Empty => match dir.read_dir() {
+                Ok(mut entries) => entries.next().is_none(),
+                Err(_) => !dir.exists(),
+            },
+            DirPredicate::Git => git(dir, &["rev-parse", "--is-inside-work-tree"])
+                .is_some_and(|out| out.trim() == "true"),
+            DirPredicate::GitClean => {
+                DirPredicate::Git.holds(dir)
+                    && git(dir, &["status", "--porcelain"]).is_some_and(|out| out.is_empty())
+            }
+        }
+    }
+}
+
+/// Runs git in the nearest existing ancestor of `dir`, returning its output if it succeeds.
+fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let existing = dir.ancestors().find(|d| d.is_dir())?;
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(existing)
+        .args(args)
+        .output()
+        .ok()?;
+
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Whether a program can be found on PATH (or at the given path, if it has a directory).
+fn on_path(binary: &str) -> bool {
+    if binary.contains(std::path::is_separator) {
+        return Path::new(binary).is_file();
+    }
+
+    env::var_os("PATH")
+        .is_some_and(|path| env::split_paths(&path).any(|dir| dir.join(binary).is_file()))
+}
+
+/// Parses a dotted version (e.g. `3.4` or `3.4.1`), ignoring trailing zero components so that
+/// `3.4` and `3.4.0` compare equal.
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    let mut parts = version
+        .trim()
+        .trim_start_matches('v')
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+
+    while parts.last() == Some(&0) {
+        parts.pop();
+    }
+
+    Some(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_compare_numerically() {
+        assert!(parse_version("3.10") > parse_version("3.9.2"));
+        assert_eq!(parse_version("v3.4.0"), parse_version("3.4"));
+        assert_eq!(parse_version("three"), None);
+
+        let newer = Requirements {
+            mkdev: Some("999.0".into()),
+            ..Default::default()
+        };
+        assert_eq!(newer.unmet(Path::new(".")).len(), 1);
+    }
+
+    #[test]
+    fn requirements_merge_without_duplicates() {
+        let mut own: Requirements = toml::from_str(
+            r#"
+            binaries = ["cargo"]
+            mkdev = "3.0"
+            dir = ["empty"]
+            "#,
+        )
+        .unwrap();
+        let base: Requirements = toml::from_str(
+            r#"
+            binaries = ["cargo", "git"]
+            mkdev = "3.2"
+            dir = ["git-clean"]
+            "#,
+        )
+        .unwrap();
+
+        own.merge(&base);
+        assert_eq!(own.binaries, ["cargo", "git"]);
+        assert_eq!(own.mkdev.as_deref(), Some("3.2"));
+        assert_eq!(own.dir, [DirPredicate::Empty, DirPredicate::GitClean]);
+    }
+}
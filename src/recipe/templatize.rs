@@ -0,0 +1,154 @@
+//! Turns the literal values in an imprinted recipe back into substitution tokens.
+//!
+//! `mk imprint --templatize name=my-service` replaces `my-service` in file contents and paths with
+//! `{{name}}`, and its case variants with the matching filter, e.g. `MyService` with
+//! `{{name | PascalCase}}`. Anything that already looks like a token is escaped, so that it is
+//! written out as it is when the recipe is evoked.
+use super::Recipe;
+use crate::content::RecipeItem;
+use crate::mkdev_error::Error;
+use crate::replacer::{InvalidTokenStrategy, ReplaceFmt};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use regex::{Captures, Regex};
+
+/// The filters that produce the case variants of a value, tried in order.
+const VARIANT_FILTERS: &[&str] = &[
+    "kebab",
+    "snake_case",
+    "PascalCase",
+    "camelCase",
+    "snake_case | upper",
+    "upper",
+    "lower",
+];
+
+/// Rewrites literal values into the tokens that produce them.
+#[derive(Debug)]
+pub struct Templatizer {
+    /// Matches any literal (longest first), or the start of a token.
+    re: Regex,
+    /// The token that replaces each literal.
+    tokens: HashMap<String, String>,
+}
+
+impl Templatizer {
+    /// Builds a templatizer from `key = value` pairs.
+    pub fn new(values: &HashMap<String, String>) -> Result<Self, Error> {
+        // A token replaces the literal it evaluates to; when variants coincide, the plainest token
+        // (which is tried first) is used
+        let mut tokens = HashMap::new();
+        let mut keys: Vec<_> = values.keys().collect();
+        keys.sort();
+        for key in keys {
+            let fmt = ReplaceFmt::new(
+                HashMap::from([(key.clone(), values[key].clone())]),
+                ("{{", "}}"),
+                InvalidTokenStrategy::Preserve,
+            );
+
+            let plain = format!("{{{{{key}}}}}");
+            let filtered = VARIANT_FILTERS
+                .iter()
+                .map(|filter| format!("{{{{{key} | {filter}}}}}"));
+            for token in std::iter::once(plain).chain(filtered) {
+                let literal = fmt.replace(&token);
+                if !literal.is_empty() {
+                    tokens.entry(literal).or_insert(token);
+                }
+            }
+        }
+
+        let mut literals: Vec<_> = tokens.keys().map(|l| regex::escape(l)).collect();
+        literals.sort_by_key(|l| std::cmp::Reverse(l.len()));
+        literals.push(regex::escape("{{"));
+
+        let re = Regex::new(&literals.join("|"))
+            .map_err(|e| Error::Invalid("--templatize value".into(), Some(vec![e.to_string()])))?;
+
+        Ok(Self { re, tokens })
+    }
+
+    /// Replaces every literal in `text` with its token, and escapes existing tokens.
+    pub fn apply(&self, text: &str) -> String {
+        self.re
+            .replace_all(text, |caps: &Captures| match self.tokens.get(&caps[0]) {
+                Some(token) => token.clone(),
+                None => format!("\\{}", &caps[0]),
+            })
+            .into_owned()
+    }
+
+    /// Applies the templatizer to a path.
+    fn apply_path(&self, path: &Path) -> PathBuf {
+        self.apply(&path.to_string_lossy()).into()
+    }
+}
+
+impl Recipe {
+    /// Replaces literal values in the recipe's contents and paths with substitution tokens.
+    ///
+    /// Binary files are left as they are, apart from their paths.
+    pub fn templatize(&mut self, templatizer: &Templatizer) {
+        for item in &mut self.contents {
+            match item {
+                RecipeItem::File(file) => {
+                    file.name = templatizer.apply_path(&file.name);
+                    file.content = templatizer.apply(&file.content);
+                }
+                RecipeItem::Binary(file) => file.name = templatizer.apply_path(&file.name),
+                RecipeItem::Symlink(link) => {
+                    link.name = templatizer.apply_path(&link.name);
+                    link.target = templatizer.apply_path(&link.target);
+                }
+                RecipeItem::Directory(dir) => *dir = templatizer.apply_path(dir),
+            }
+        }
+
+        self.contents.sort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn templatizer(values: &[(&str, &str)]) -> Templatizer {
+        let values = values
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Templatizer::new(&values).unwrap()
+    }
+
+    #[test]
+    fn case_variants_become_filtered_tokens() {
+        let t = templatizer(&[("name", "my-service"), ("year", "2025")]);
+
+        assert_eq!(
+            t.apply("my-service: MyService::new(), my_service, MY_SERVICE (c) 2025"),
+            "{{name}}: {{name | PascalCase}}::new(), {{name | snake_case}}, \
+             {{name | snake_case | upper}} (c) {{year}}"
+        );
+    }
+
+    #[test]
+    fn templatized_text_evokes_to_the_original() {
+        let t = templatizer(&[("name", "my-service")]);
+        let original = "let {{x}} = MyService; // my-service\n";
+        let templatized = t.apply(original);
+        assert_eq!(
+            templatized,
+            "let \\{{x}} = {{name | PascalCase}}; // {{name}}\n"
+        );
+
+        let fmt = ReplaceFmt::new(
+            HashMap::from([("name".into(), "my-service".into())]),
+            ("{{", "}}"),
+            InvalidTokenStrategy::Preserve,
+        );
+        assert_eq!(fmt.replace(&templatized), original);
+    }
+}
@@ -0,0 +1,281 @@
+//! An undo log for the filesystem changes made by `mk evoke`.
+//!
+//! Every directory created and every file written through a `Transaction` is recorded, so that a
+//! failed evoke can put the target directory back exactly as it found it.
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A single change that can be undone.
+#[derive(Debug)]
+enum Change {
+    /// A directory that did not exist before.
+    CreatedDir(PathBuf),
+    /// A file that did not exist before.
+    CreatedFile(PathBuf),
+    /// A file that existed before, along with its original contents.
+    OverwrittenFile(PathBuf, Vec<u8>),
+    /// A file that was moved from the first path to the second.
+    Renamed(PathBuf, PathBuf),
+    /// A symlink that was removed, along with its target.
+    RemovedSymlink(PathBuf, PathBuf),
+    /// A file whose permissions were changed, along with its original permissions.
+    ChangedPermissions(PathBuf, fs::Permissions),
+}
+
+/// Records filesystem changes so that they can be rolled back.
+#[derive(Debug, Default)]
+pub struct Transaction {
+    changes: Vec<Change>,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a directory and all of its missing parents, recording each one that was created.
+    pub fn create_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        let mut missing: Vec<_> = path.ancestors().take_while(|p| !p.is_dir()).collect();
+        missing.reverse();
+
+        for dir in missing {
+            // An empty path is the (already existing) cwd
+            if dir.as_os_str().is_empty() {
+                continue;
+            }
+
+            fs::create_dir(dir)?;
+            self.changes.push(Change::CreatedDir(dir.to_path_buf()));
+        }
+
+        Ok(())
+    }
+
+    /// Writes a file, recording its original contents if it already existed.
+    pub fn write(&mut self, path: &Path, content: impl AsRef<[u8]>) -> io::Result<()> {
+        let change = match path.is_file() {
+            true => Change::OverwrittenFile(path.to_path_buf(), fs::read(path)?),
+            false => Change::CreatedFile(path.to_path_buf()),
+        };
+
+        fs::write(path, content)?;
+        self.changes.push(change);
+
+        Ok(())
+    }
+
+    /// Appends to a file, recording its original contents.
+    pub fn append(&mut self, path: &Path, content: &str) -> io::Result<()> {
+        let original = fs::read(path)?;
+        self.changes
+            .push(Change::OverwrittenFile(path.to_path_buf(), original));
+
+        let mut file = fs::OpenOptions::new().append(true).open(path)?;
+        file.write_all(content.as_bytes())
+    }
+
+    /// Renames a file, recording the original contents of the destination if it already existed.
+    pub fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        // Undoing the rename restores `from`, but anything it replaced has to be restored too
+        if to.is_file() {
+            let original = fs::read(to)?;
+            self.changes
+                .push(Change::OverwrittenFile(to.to_path_buf(), original));
+        }
+
+        fs::rename(from, to)?;
+        self.changes
+            .push(Change::Renamed(from.to_path_buf(), to.to_path_buf()));
+
+        Ok(())
+    }
+
+    /// Creates a symlink at `path` pointing to `target`, replacing any file or symlink already
+    /// there and recording what it was.
+    #[cfg(unix)]
+    pub fn symlink(&mut self, target: &Path, path: &Path) -> io::Result<()> {
+        if let Ok(meta) = path.symlink_metadata() {
+            let change = match meta.is_symlink() {
+                true => Change::RemovedSymlink(path.to_path_buf(), fs::read_link(path)?),
+                false => Change::OverwrittenFile(path.to_path_buf(), fs::read(path)?),
+            };
+            fs::remove_file(path)?;
+            self.changes.push(change);
+        }
+
+        std::os::unix::fs::symlink(target, path)?;
+        self.changes.push(Change::CreatedFile(path.to_path_buf()));
+
+        Ok(())
+    }
+
+    /// Symlinks can't be created portably on other platforms.
+    #[cfg(not(unix))]
+    pub fn symlink(&mut self, _target: &Path, path: &Path) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "cannot create symlink '{}' on this platform",
+                path.display()
+            ),
+        ))
+    }
+
+    /// Sets the Unix permission bits of a file, recording its original permissions.
+    #[cfg(unix)]
+    pub fn set_mode(&mut self, path: &Path, mode: u32) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let original = fs::metadata(path)?.permissions();
+        if original.mode() & 0o777 == mode {
+            return Ok(());
+        }
+
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        self.changes
+            .push(Change::ChangedPermissions(path.to_path_buf(), original));
+
+        Ok(())
+    }
+
+    /// Other platforms have no permission bits, so this does nothing.
+    #[cfg(not(unix))]
+    pub fn set_mode(&mut self, _path: &Path, _mode: u32) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Applies `mode` to a file without giving anyone access they don't already have: it can
+    /// only take permissions away, or make the file executable by whoever can read it.
+    ///
+    /// A new file starts out with the user's default permissions (see umask), so a mode from a
+    /// recipe is limited by the umask, and an existing file is limited by its own permissions.
+    #[cfg(unix)]
+    pub fn restrict_mode(&mut self, path: &Path, mode: u32) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let current = fs::metadata(path)?.permissions().mode() & 0o777;
+        let readable = (current & 0o444) >> 2;
+        self.set_mode(path, (current & mode & 0o666) | (mode & readable & 0o111))
+    }
+
+    /// Other platforms have no permission bits, so this does nothing.
+    #[cfg(not(unix))]
+    pub fn restrict_mode(&mut self, _path: &Path, _mode: u32) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Undoes every recorded change, most recent first.
+    ///
+    /// Rolling back continues past failures so that as much as possible is restored; the first
+    /// error encountered is returned.
+    pub fn rollback(self) -> io::Result<()> {
+        let mut first_err = None;
+
+        for change in self.changes.into_iter().rev() {
+            let undone = match change {
+                Change::CreatedDir(dir) => fs::remove_dir(dir),
+                Change::CreatedFile(file) => fs::remove_file(file),
+                Change::OverwrittenFile(file, original) => fs::write(file, original),
+                Change::Renamed(from, to) => fs::rename(to, from),
+                Change::ChangedPermissions(file, original) => fs::set_permissions(file, original),
+                #[cfg(unix)]
+                Change::RemovedSymlink(link, target) => std::os::unix::fs::symlink(target, link),
+                #[cfg(not(unix))]
+                Change::RemovedSymlink(..) => unreachable!("symlinks are only created on unix"),
+            };
+
+            if let Err(why) = undone {
+                first_err.get_or_insert(why);
+            }
+        }
+
+        match first_err {
+            Some(why) => Err(why),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDir;
+
+    #[test]
+    fn rollback_restores_original_state() {
+        let root = TestDir::new("tx");
+        fs::write(root.join("existing.txt"), "original").unwrap();
+
+        let mut tx = Transaction::new();
+        tx.create_dir_all(&root.join("a/b")).unwrap();
+        tx.write(&root.join("a/b/new.txt"), "new").unwrap();
+        tx.write(&root.join("existing.txt"), "changed").unwrap();
+        tx.rollback().unwrap();
+
+        assert!(!root.join("a").exists());
+        assert_eq!(
+            fs::read_to_string(root.join("existing.txt")).unwrap(),
+            "original"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rollback_restores_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = TestDir::new("tx-mode");
+        let script = root.join("setup.sh");
+        fs::write(&script, "").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let mut tx = Transaction::new();
+        tx.set_mode(&script, 0o755).unwrap();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&script), 0o755);
+
+        tx.rollback().unwrap();
+        assert_eq!(mode(&script), 0o644);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn restricted_modes_never_grant_access() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = TestDir::new("tx-restrict");
+        let file = root.join("file");
+        fs::write(&file, "").unwrap();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        let restrict = |from, to| {
+            fs::set_permissions(&file, fs::Permissions::from_mode(from)).unwrap();
+            Transaction::new().restrict_mode(&file, to).unwrap();
+            mode(&file)
+        };
+
+        // e.g. a new file under `umask 077`
+        assert_eq!(restrict(0o600, 0o644), 0o600);
+        assert_eq!(restrict(0o600, 0o755), 0o700);
+        assert_eq!(restrict(0o644, 0o755), 0o755);
+        assert_eq!(restrict(0o755, 0o644), 0o644);
+        assert_eq!(restrict(0o644, 0o600), 0o600);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rollback_restores_replaced_symlinks() {
+        let root = TestDir::new("tx-link");
+        std::os::unix::fs::symlink("old", root.join("link")).unwrap();
+        fs::write(root.join("file"), "original").unwrap();
+
+        let mut tx = Transaction::new();
+        tx.symlink(Path::new("new"), &root.join("link")).unwrap();
+        tx.symlink(Path::new("new"), &root.join("file")).unwrap();
+        assert_eq!(fs::read_link(root.join("file")).unwrap(), Path::new("new"));
+
+        tx.rollback().unwrap();
+        assert_eq!(fs::read_link(root.join("link")).unwrap(), Path::new("old"));
+        assert_eq!(fs::read_to_string(root.join("file")).unwrap(), "original");
+    }
+}
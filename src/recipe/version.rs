@@ -7,6 +7,7 @@ use crate::content::RecipeItem;
 
 use super::Language;
 use super::Recipe;
+use super::Requirements;
 
 use serde::{Deserialize, Serialize};
 
@@ -14,7 +15,7 @@ use serde::{Deserialize, Serialize};
 #[derive(Deserialize)]
 #[serde(untagged)]
 enum RecipeVersions {
-    V2(Recipe),
+    V2(Box<Recipe>),
     V1(RecipeV1),
 }
 
@@ -22,10 +23,12 @@ enum RecipeVersions {
 
 /// Deserialises a known version of the recipe format, and converts it to the most recent version.
 ///
-/// Returns None if the recipe data doesn't match any known format.
+/// Recipes are read as TOML (as they are saved), or failing that as JSON (as they are printed by
+/// `mk list --type json`). Returns None if the recipe data doesn't match any known format.
 pub fn deserialise_recipe(value: &str) -> Option<Recipe> {
     toml::from_str::<RecipeVersions>(value)
         .ok()
+        .or_else(|| serde_json::from_str::<RecipeVersions>(value).ok())
         .map(Recipe::from)
 }
 
@@ -33,7 +36,7 @@ impl From<RecipeVersions> for Recipe {
     fn from(value: RecipeVersions) -> Self {
         use RecipeVersions::*;
         match value {
-            V2(r) => r,
+            V2(r) => *r,
             V1(r) => {
                 // V1 has hardcoded string languages, so those need to be converted to a language
                 // struct if possible
@@ -50,7 +53,17 @@ impl From<RecipeVersions> for Recipe {
                     name: r.name,
                     description: r.description,
                     languages,
+                    extends: None,
+                    remove: vec![],
+                    patches: vec![],
+                    includes: vec![],
+                    post_evoke: vec![],
+                    requires: Requirements::default(),
+                    subs: Default::default(),
+                    variables: vec![],
                     contents,
+                    source: None,
+                    unresolved: None,
                 }
             }
         }
@@ -97,6 +110,7 @@ fn flatten_v1_recursive(old: Vec<ContentV1>) -> Vec<RecipeItem> {
                 let new_f = File {
                     name: f.name.into(),
                     content: f.content,
+                    mode: None,
                 };
 
                 out.push(RecipeItem::File(new_f));
@@ -2,7 +2,23 @@
 //!
 //! This module is used to implement mkdev's recipe substitutions during `mk evoke` as well as
 //! formatting recipes for the default `mk list` behaviour.
+//!
+//! Besides plain `{{key}}` tokens, three kinds of block are supported:
+//! - `{{#if key}}...{{/if}}` keeps its body only if `key` is truthy;
+//! - `{{#unless key}}...{{/unless}}` keeps its body only if `key` is not truthy;
+//! - `{{#each key}}...{{/each}}` repeats its body for each comma or newline separated item in
+//!   `key`, which is available inside the body as `{{this}}`.
+//!
+//! A value is truthy unless it is empty, `false`, `no`, or `0`. Block tags that sit on a line of
+//! their own take the whole line with them. With `InvalidTokenStrategy::Preserve`, a block whose
+//! key is not defined is kept as it is written (with its body rendered), like an undefined token.
+//!
+//! Token values can be transformed with filters, e.g. `{{name | kebab}}`; see [`filters`].
 #![allow(dead_code)]
+mod filters;
+
+use filters::Expression;
+
 use std::collections::HashMap;
 
 /// The primary interface for the formatter.
@@ -41,6 +57,16 @@ impl ReplaceFmt {
     pub fn replace_with<F>(&self, src: &str, resolver: F) -> String
     where
         F: Fn(&str) -> Option<String>,
+    {
+        self.replace_escaped(src, resolver, |value| value)
+    }
+
+    /// Like `replace_with`, but what each token evaluates to (after its filters) is passed through
+    /// `escape` before it is written out, e.g. so that it can't be mistaken for shell syntax.
+    pub fn replace_escaped<F, E>(&self, src: &str, resolver: F, escape: E) -> String
+    where
+        F: Fn(&str) -> Option<String>,
+        E: Fn(String) -> String,
     {
         let parser = Parser {
             source: src.chars().collect(),
@@ -48,36 +74,121 @@ impl ReplaceFmt {
             delims: self.delims.clone(),
         };
 
-        let tokens = parser.parse();
-
-        tokens
-            .into_iter()
-            .filter_map(|token| match token {
-                Segment::Text(text) => Some(text),
-                Segment::Token(key) => {
-                    let def = self.subs.get(&key);
-                    match def {
-                        // Special case for reserved names.
-                        Some(val) if key.starts_with("mk::") => Some(val.to_string()),
-                        // User-defined resolution
-                        Some(val) => resolver(val),
-                        // Strategy-defined resolution for failed lookups.
-                        None => match self.strategy {
-                            InvalidTokenStrategy::PassThrough => Some(key),
-                            InvalidTokenStrategy::Preserve => Some(format!(
-                                "{}{}{}",
-                                self.delims.open.iter().collect::<String>(),
-                                key,
-                                self.delims.close.iter().collect::<String>()
-                            )),
-                            InvalidTokenStrategy::Ignore => None,
-                        },
+        let segments = parser.parse();
+
+        self.render(&segments, &resolver, &escape, &HashMap::new())
+    }
+
+    /// Renders parsed segments, with `locals` taking precedence over the internal mapping.
+    fn render<F, E>(
+        &self,
+        segments: &[Segment],
+        resolver: &F,
+        escape: &E,
+        locals: &HashMap<&str, &str>,
+    ) -> String
+    where
+        F: Fn(&str) -> Option<String>,
+        E: Fn(String) -> String,
+    {
+        segments
+            .iter()
+            .filter_map(|segment| match segment {
+                Segment::Text(text) => Some(text.clone()),
+                Segment::Token(key) if !self.is_defined(key, locals) => match self.strategy {
+                    // Strategy-defined resolution for failed lookups.
+                    InvalidTokenStrategy::PassThrough => Some(key.clone()),
+                    InvalidTokenStrategy::Preserve => Some(format!(
+                        "{}{}{}",
+                        self.delims.open.iter().collect::<String>(),
+                        key,
+                        self.delims.close.iter().collect::<String>()
+                    )),
+                    InvalidTokenStrategy::Ignore => None,
+                },
+                Segment::Token(key) => self.lookup(key, resolver, locals).map(escape),
+                // Other template languages use the same syntax, so their blocks are kept as well
+                Segment::Block(block)
+                    if !self.is_defined(&block.key, locals)
+                        && matches!(self.strategy, InvalidTokenStrategy::Preserve) =>
+                {
+                    let body = self.render(&block.body, resolver, escape, locals);
+                    Some(format!("{}{body}{}", block.open, block.close))
+                }
+                Segment::Block(block) => {
+                    let value = self.lookup(&block.key, resolver, locals);
+                    let value = value.as_deref().unwrap_or_default();
+
+                    match block.kind {
+                        BlockKind::If if truthy(value) => {
+                            Some(self.render(&block.body, resolver, escape, locals))
+                        }
+                        BlockKind::Unless if !truthy(value) => {
+                            Some(self.render(&block.body, resolver, escape, locals))
+                        }
+                        BlockKind::If | BlockKind::Unless => None,
+                        BlockKind::Each => Some(
+                            items(value)
+                                .map(|item| {
+                                    let mut locals = locals.clone();
+                                    locals.insert("this", item);
+                                    self.render(&block.body, resolver, escape, &locals)
+                                })
+                                .collect(),
+                        ),
                     }
                 }
             })
             .collect::<Vec<_>>()
             .join("")
     }
+
+    /// Returns `true` if `token` has a value: its key is defined, either as a local or in the
+    /// internal mapping, or it has a default.
+    ///
+    /// Tokens with invalid filters are never defined.
+    fn is_defined(&self, token: &str, locals: &HashMap<&str, &str>) -> bool {
+        Expression::parse(token).is_some_and(|expr| {
+            locals.contains_key(expr.key.as_str())
+                || self.subs.contains_key(&expr.key)
+                || expr.has_default()
+        })
+    }
+
+    /// Evaluates `token`, applying `resolver` to values from the internal mapping and then
+    /// applying the token's filters.
+    fn lookup<F>(&self, token: &str, resolver: &F, locals: &HashMap<&str, &str>) -> Option<String>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let expr = Expression::parse(token)?;
+        let key = expr.key.as_str();
+
+        let value = match (locals.get(key), self.subs.get(key)) {
+            (Some(local), _) => Some(local.to_string()),
+            // Special case for reserved names.
+            (None, Some(val)) if key.starts_with("mk::") => Some(val.to_string()),
+            // User-defined resolution
+            (None, Some(val)) => resolver(val),
+            (None, None) => None,
+        };
+
+        expr.apply(value)
+    }
+}
+
+/// Returns `true` unless a value is empty, `false`, `no`, or `0`.
+fn truthy(value: &str) -> bool {
+    let value = value.trim().to_lowercase();
+    !matches!(value.as_str(), "" | "false" | "no" | "0")
+}
+
+/// Splits a value into the items an `each` block iterates over.
+fn items(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split([',', '\n'])
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
 }
 
 /// A single-use parser for a format string.
@@ -96,11 +207,86 @@ struct Parser {
 impl Parser {
     /// Parses out the the Tokens, consuming the parser.
     pub fn parse(mut self) -> Vec<Segment> {
+        self.parse_body(None).0
+    }
+
+    /// Parses segments until `closer` (e.g. `/if`) or the end of the buffer is found.
+    ///
+    /// Returns the segments, and the text of the closing tag if it was found and consumed.
+    fn parse_body(&mut self, closer: Option<&str>) -> (Vec<Segment>, Option<String>) {
         let mut out = vec![];
         while !self.at_end() {
-            out.push(self.next_segment());
+            let start = self.curr;
+            let segment = self.next_segment();
+
+            let Segment::Token(tag) = &segment else {
+                out.push(segment);
+                continue;
+            };
+
+            if closer == Some(tag.as_str()) {
+                let from = self.trim_standalone(start, &mut out);
+                return (out, Some(self.source[from..self.curr].iter().collect()));
+            }
+
+            let Some((kind, key)) = BlockKind::parse_tag(tag) else {
+                out.push(segment);
+                continue;
+            };
+
+            let tag_text = self.source[start..self.curr].iter().collect();
+            let from = self.trim_standalone(start, &mut out);
+            let open = self.source[from..self.curr].iter().collect();
+            let (body, close) = self.parse_body(Some(kind.closer()));
+
+            // Unclosed blocks are treated as text, like unclosed tokens
+            match close {
+                Some(close) => out.push(Segment::Block(Block {
+                    kind,
+                    key,
+                    body,
+                    open,
+                    close,
+                })),
+                None => {
+                    out.push(Segment::Text(tag_text));
+                    out.extend(body);
+                }
+            }
         }
-        out
+        (out, None)
+    }
+
+    /// If the tag that started at `start` (and ends at the cursor) is alone on its line, removes
+    /// the rest of the line: the indentation before it in `out`, and everything up to and
+    /// including the newline after it.
+    ///
+    /// Returns where the removed text starts, which is `start` if nothing else was removed.
+    fn trim_standalone(&mut self, start: usize, out: &mut [Segment]) -> usize {
+        let is_blank = |c: &char| *c == ' ' || *c == '\t';
+
+        let line_start = self.source[..start]
+            .iter()
+            .rposition(|c| *c == '\n')
+            .map_or(0, |i| i + 1);
+        let line_end = self.source[self.curr..]
+            .iter()
+            .position(|c| *c == '\n')
+            .map_or(self.source.len(), |i| self.curr + i);
+
+        let before_blank = self.source[line_start..start].iter().all(is_blank);
+        let after_blank = self.source[self.curr..line_end].iter().all(is_blank);
+        if !before_blank || !after_blank {
+            return start;
+        }
+
+        if start > line_start
+            && let Some(Segment::Text(text)) = out.last_mut()
+        {
+            text.truncate(text.trim_end_matches([' ', '\t']).len());
+        }
+        self.curr = (line_end + 1).min(self.source.len());
+        line_start
     }
 
     /// Yields the next contiguous segment from the source buffer.
@@ -203,6 +389,50 @@ struct Delimiters {
 enum Segment {
     Token(String),
     Text(String),
+    Block(Block),
+}
+
+/// A block of segments that is conditionally rendered or repeated based on the value of `key`.
+#[derive(Clone, Debug)]
+struct Block {
+    kind: BlockKind,
+    key: String,
+    body: Vec<Segment>,
+    /// The opening and closing tags as they were written, with any line they took with them.
+    open: String,
+    close: String,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum BlockKind {
+    If,
+    Unless,
+    Each,
+}
+
+impl BlockKind {
+    /// Parses an opening block tag (e.g. `#if key`) into its kind and key.
+    fn parse_tag(tag: &str) -> Option<(Self, String)> {
+        let (kind, key) = tag.strip_prefix('#')?.split_once(' ')?;
+        let kind = match kind {
+            "if" => BlockKind::If,
+            "unless" => BlockKind::Unless,
+            "each" => BlockKind::Each,
+            _ => return None,
+        };
+
+        let key = key.trim();
+        (!key.is_empty()).then(|| (kind, key.to_string()))
+    }
+
+    /// The tag that closes this kind of block.
+    fn closer(self) -> &'static str {
+        match self {
+            BlockKind::If => "/if",
+            BlockKind::Unless => "/unless",
+            BlockKind::Each => "/each",
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -325,4 +555,62 @@ mod tests {
         let fmt = make_fmt(&[("a", "b")], ("{", "}"), Ignore);
         assert_eq!(fmt.replace("hello\\"), "hello\\");
     }
+
+    #[test]
+    fn if_block_follows_truthiness() {
+        let fmt = make_fmt(&[("yes", "true"), ("no", "false")], ("{{", "}}"), Preserve);
+        assert_eq!(fmt.replace("a{{#if yes}}b{{/if}}c"), "abc");
+        assert_eq!(fmt.replace("a{{#if no}}b{{/if}}c"), "ac");
+    }
+
+    #[test]
+    fn unless_block_inverts_if() {
+        let fmt = make_fmt(&[("no", "0")], ("{{", "}}"), Preserve);
+        assert_eq!(fmt.replace("{{#unless no}}b{{/unless}}"), "b");
+    }
+
+    #[test]
+    fn undefined_blocks_are_preserved() {
+        let fmt = make_fmt(&[("name", "site")], ("{{", "}}"), Preserve);
+        assert_eq!(
+            fmt.replace("a{{#if missing}}{{name}}{{/if}}c"),
+            "a{{#if missing}}site{{/if}}c"
+        );
+        let page = "<ul>\n  {{#each items}}\n- {{this}}\n  {{/each}}\n</ul>\n";
+        assert_eq!(fmt.replace(page), page);
+
+        // Other strategies still treat an undefined key as false
+        let fmt = make_fmt(&[], ("{{", "}}"), Ignore);
+        assert_eq!(fmt.replace("a{{#if missing}}b{{/if}}c"), "ac");
+        assert_eq!(fmt.replace("{{#unless missing}}b{{/unless}}"), "b");
+    }
+
+    #[test]
+    fn each_block_repeats_body() {
+        let fmt = make_fmt(&[("list", "a, b,c")], ("{{", "}}"), Preserve);
+        assert_eq!(
+            fmt.replace("{{#each list}}[{{this}}]{{/each}}"),
+            "[a][b][c]"
+        );
+    }
+
+    #[test]
+    fn nested_blocks() {
+        let fmt = make_fmt(&[("on", "yes"), ("list", "a,b")], ("{{", "}}"), Preserve);
+        let src = "{{#if on}}{{#each list}}{{this}}{{#if on}}!{{/if}}{{/each}}{{/if}}";
+        assert_eq!(fmt.replace(src), "a!b!");
+    }
+
+    #[test]
+    fn standalone_block_tags_remove_their_lines() {
+        let fmt = make_fmt(&[("docker", "yes"), ("ci", "no")], ("{{", "}}"), Preserve);
+        let src = "a\n  {{#if docker}}\nb\n  {{/if}}\n{{#if ci}}\nc\n{{/if}}\nd\n";
+        assert_eq!(fmt.replace(src), "a\nb\nd\n");
+    }
+
+    #[test]
+    fn unclosed_block_treated_as_text() {
+        let fmt = make_fmt(&[("a", "b")], ("{{", "}}"), Preserve);
+        assert_eq!(fmt.replace("{{#if a}}{{a}}"), "{{#if a}}b");
+    }
 }
@@ -0,0 +1,272 @@
+//! Filters that transform the value of a token, e.g. `{{name | snake_case}}`.
+//!
+//! A token is a key followed by any number of `|`-separated filters, which are applied left to
+//! right. Filters that take arguments separate them from the filter name with a `:`, and from
+//! each other with a `,`; arguments may be quoted to include spaces, commas, or pipes.
+
+/// A parsed token: the key to look up, and the filters to apply to its value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expression {
+    pub key: String,
+    pub filters: Vec<Filter>,
+}
+
+/// A transformation applied to a value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Filter {
+    /// `upper`: UPPER CASE
+    Upper,
+    /// `lower`: lower case
+    Lower,
+    /// `snake_case`: snake_case
+    Snake,
+    /// `kebab`: kebab-case
+    Kebab,
+    /// `PascalCase`: PascalCase
+    Pascal,
+    /// `camelCase`: camelCase
+    Camel,
+    /// `default: "value"`: used when the key is undefined or its value is empty
+    Default(String),
+    /// `replace: "from", "to"`: replaces every occurrence of `from` with `to`
+    Replace(String, String),
+}
+
+impl Expression {
+    /// Parses a token into an expression.
+    ///
+    /// Returns `None` if the token uses an unknown filter or passes it the wrong arguments.
+    pub fn parse(token: &str) -> Option<Self> {
+        let mut parts = split_unquoted(token, '|').into_iter();
+        let key = parts.next()?.trim().to_string();
+        let filters = parts.map(|f| Filter::parse(&f)).collect::<Option<_>>()?;
+
+        Some(Self { key, filters })
+    }
+
+    /// Returns `true` if the expression has a value even when its key is undefined.
+    pub fn has_default(&self) -> bool {
+        self.filters.iter().any(|f| matches!(f, Filter::Default(_)))
+    }
+
+    /// Applies every filter, in order, to a value.
+    pub fn apply(&self, value: Option<String>) -> Option<String> {
+        self.filters
+            .iter()
+            .fold(value, |value, filter| filter.apply(value))
+    }
+}
+
+impl Filter {
+    /// Parses a single filter, e.g. `snake_case` or `replace: " ", "_"`.
+    fn parse(filter: &str) -> Option<Self> {
+        let (name, args) = match filter.split_once(':') {
+            Some((name, args)) => (name.trim(), split_unquoted(args, ',')),
+            None => (filter.trim(), vec![]),
+        };
+        let args: Vec<String> = args.iter().map(|a| unquote(a.trim())).collect();
+
+        match (name, args.as_slice()) {
+            ("upper", []) => Some(Filter::Upper),
+            ("lower", []) => Some(Filter::Lower),
+            ("snake_case", []) => Some(Filter::Snake),
+            ("kebab" | "kebab-case", []) => Some(Filter::Kebab),
+            ("PascalCase", []) => Some(Filter::Pascal),
+            ("camelCase", []) => Some(Filter::Camel),
+            ("default", [default]) => Some(Filter::Default(default.clone())),
+            ("replace", [from, to]) => Some(Filter::Replace(from.clone(), to.clone())),
+            _ => None,
+        }
+    }
+
+    /// Applies the filter to a value. Only `default` does anything to a missing value.
+    fn apply(&self, value: Option<String>) -> Option<String> {
+        if let Filter::Default(default) = self {
+            return match value {
+                Some(value) if !value.is_empty() => Some(value),
+                _ => Some(default.clone()),
+            };
+        }
+
+        let value = value?;
+        let out = match self {
+            Filter::Upper => value.to_uppercase(),
+            Filter::Lower => value.to_lowercase(),
+            Filter::Snake => words(&value).join("_").to_lowercase(),
+            Filter::Kebab => words(&value).join("-").to_lowercase(),
+            Filter::Pascal => words(&value).iter().map(|w| capitalise(w)).collect(),
+            Filter::Camel => {
+                let words = words(&value);
+                let mut out = words.first().map(|w| w.to_lowercase()).unwrap_or_default();
+                words
+                    .iter()
+                    .skip(1)
+                    .for_each(|w| out.push_str(&capitalise(w)));
+                out
+            }
+            Filter::Replace(from, to) => value.replace(from.as_str(), to),
+            Filter::Default(_) => unreachable!("handled above"),
+        };
+
+        Some(out)
+    }
+}
+
+/// Splits a value into words at non-alphanumeric characters and at changes of case, so that
+/// `my-crate`, `my_crate`, `MyCrate`, and `myCrate` all give `["my", "crate"]` (modulo case).
+///
+/// Runs of capitals are kept together as an acronym, so `HTTPServer` gives `["HTTP", "Server"]`.
+fn words(value: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let chars: Vec<char> = value.chars().collect();
+
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            continue;
+        }
+
+        let prev = i.checked_sub(1).map(|i| chars[i]);
+        let next = chars.get(i + 1);
+        let boundary = c.is_uppercase()
+            && prev.is_some_and(|p| {
+                p.is_lowercase()
+                    || p.is_numeric()
+                    || (p.is_uppercase() && next.is_some_and(|n| n.is_lowercase()))
+            });
+
+        if boundary && !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        word.push(c);
+    }
+
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    words
+}
+
+/// Upper cases the first character of a word, and lower cases the rest.
+fn capitalise(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first
+            .to_uppercase()
+            .chain(chars.flat_map(char::to_lowercase))
+            .collect(),
+        None => String::new(),
+    }
+}
+
+/// Splits `s` at every `sep` that is not inside double quotes.
+fn split_unquoted(s: &str, sep: char) -> Vec<String> {
+    let mut parts = vec![];
+    let mut part = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for c in s.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == sep && !quoted => {
+                parts.push(std::mem::take(&mut part));
+                continue;
+            }
+            _ => {}
+        }
+        part.push(c);
+    }
+    parts.push(part);
+
+    parts
+}
+
+/// Strips the quotes (and escapes) from a quoted argument; unquoted arguments are returned as is.
+fn unquote(arg: &str) -> String {
+    let Some(inner) = arg.strip_prefix('"').and_then(|a| a.strip_suffix('"')) else {
+        return arg.to_string();
+    };
+
+    let mut out = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(token: &str, value: Option<&str>) -> Option<String> {
+        Expression::parse(token)
+            .expect("token should parse")
+            .apply(value.map(String::from))
+    }
+
+    #[test]
+    fn case_filters() {
+        assert_eq!(
+            apply("n | snake_case", Some("MyCrate")).unwrap(),
+            "my_crate"
+        );
+        assert_eq!(apply("n | kebab", Some("my_crate")).unwrap(), "my-crate");
+        assert_eq!(
+            apply("n | PascalCase", Some("my-crate")).unwrap(),
+            "MyCrate"
+        );
+        assert_eq!(apply("n | camelCase", Some("my crate")).unwrap(), "myCrate");
+        assert_eq!(apply("n | upper", Some("my-crate")).unwrap(), "MY-CRATE");
+        assert_eq!(
+            apply("n | snake_case", Some("HTTPServer2")).unwrap(),
+            "http_server2"
+        );
+    }
+
+    #[test]
+    fn default_filter() {
+        assert_eq!(apply(r#"y | default: "2026""#, None).unwrap(), "2026");
+        assert_eq!(apply(r#"y | default: "2026""#, Some("")).unwrap(), "2026");
+        assert_eq!(
+            apply(r#"y | default: "2026""#, Some("1999")).unwrap(),
+            "1999"
+        );
+    }
+
+    #[test]
+    fn replace_filter_with_quoted_arguments() {
+        let token = r#"d | replace: " | ", "_" | upper"#;
+        assert_eq!(apply(token, Some("a | b")).unwrap(), "A_B");
+    }
+
+    #[test]
+    fn filters_chain_left_to_right() {
+        assert_eq!(
+            apply("n | upper | kebab", Some("MyCrate")).unwrap(),
+            "mycrate"
+        );
+        assert_eq!(
+            apply("n | kebab | upper", Some("MyCrate")).unwrap(),
+            "MY-CRATE"
+        );
+    }
+
+    #[test]
+    fn invalid_filters_do_not_parse() {
+        assert!(Expression::parse("n | shout").is_none());
+        assert!(Expression::parse("n | replace: \"a\"").is_none());
+        assert!(Expression::parse("n | upper: \"a\"").is_none());
+    }
+}
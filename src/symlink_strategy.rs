@@ -0,0 +1,13 @@
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, ValueEnum, Default, PartialEq, Eq)]
+/// What `mk imprint` should do with symlinks. Used by the --symlinks flag
+pub enum SymlinkStrategy {
+    /// Leave symlinks out of the recipe
+    #[default]
+    Skip,
+    /// Store the contents of whatever the symlink points to
+    Follow,
+    /// Store the symlink itself, and recreate it on evoke
+    Preserve,
+}
@@ -0,0 +1,72 @@
+//! Helpers shared by the unit tests.
+use crate::content::{File, RecipeItem};
+use crate::recipe::{Recipe, Requirements};
+
+use std::env;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// A directory for a test to work in. It is removed with everything in it when dropped, even if
+/// the test panics.
+#[derive(Debug)]
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    /// Creates an empty directory. Each test uses its own `name`, since tests run at the same time.
+    pub fn new(name: &str) -> Self {
+        let dir = env::temp_dir().join(format!("mkdev-{name}-{}", process::id()));
+        // Left over from a test run that was killed
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        Self(dir)
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A text file in a recipe.
+pub fn file(name: &str, content: &str) -> RecipeItem {
+    RecipeItem::File(File {
+        name: name.into(),
+        content: content.to_string(),
+        mode: None,
+    })
+}
+
+/// A recipe that only has a text file for each `(path, content)`.
+pub fn recipe(name: &str, files: &[(&str, &str)]) -> Recipe {
+    Recipe {
+        name: name.into(),
+        description: String::new(),
+        languages: vec![],
+        extends: None,
+        remove: vec![],
+        patches: vec![],
+        includes: vec![],
+        post_evoke: vec![],
+        requires: Requirements::default(),
+        subs: Default::default(),
+        variables: vec![],
+        contents: files
+            .iter()
+            .map(|(name, content)| file(name, content))
+            .collect(),
+        source: None,
+        unresolved: None,
+    }
+}